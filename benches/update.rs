@@ -0,0 +1,44 @@
+use cloth::{Cloth, ClothConfig, SimParams, FIXED_DT};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glam::vec2;
+
+// Times one fixed step of the serial solver at a few grid sizes. The cloth keeps falling and
+// settling between samples, which is the same work a running sim does every frame.
+fn bench_update(c: &mut Criterion) {
+    let params = SimParams { iterations: 10, ..SimParams::default() };
+    let mut group = c.benchmark_group("update");
+    for size in [16, 32, 64] {
+        let mut config = ClothConfig::new(size, size, 10.0, vec2(0.0, 0.0));
+        config.jitter = 0.2;
+        config.seed = 1;
+        let mut cloth = Cloth::new(&config);
+        group.throughput(Throughput::Elements(cloth.particles().len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(format!("{size}x{size}")), |b| {
+            b.iter(|| cloth.update(FIXED_DT, &params))
+        });
+    }
+    group.finish();
+}
+
+// Runs the same steps with the serial and the parallel solver side by side, so the speedup
+// shows up as the ratio between the two lines of each size.
+fn bench_parallel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel");
+    for size in [32, 64, 128] {
+        let mut config = ClothConfig::new(size, size, 15.0, vec2(0.0, 0.0));
+        config.seed = 1;
+        group.throughput(Throughput::Elements((size * size) as u64));
+        for parallel in [false, true] {
+            let params = SimParams { iterations: 20, parallel, ..SimParams::default() };
+            let mut cloth = Cloth::new(&config);
+            let name = if parallel { "parallel" } else { "serial" };
+            group.bench_function(BenchmarkId::new(name, format!("{size}x{size}")), |b| {
+                b.iter(|| cloth.update(FIXED_DT, &params))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_update, bench_parallel);
+criterion_main!(benches);
@@ -0,0 +1,2476 @@
+//! Headless cloth simulation: particles, springs and the Verlet solver. It only depends on glam
+//! for its vectors, so it can run in another engine or on a server. Build a `Cloth` from a
+//! `ClothConfig`, then advance it with `Cloth::update` and the `SimParams` for each step.
+//! Drawing and input live in the binary, behind the default `app` feature.
+
+use glam::{vec2, Vec2};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::Write;
+
+pub const FIXED_DT: f32 = 1.0 / 120.0;
+// How close to an obstacle's surface a particle still counts as touching it, in world units,
+// so one the iterations pushed exactly onto the surface gets its response.
+const CONTACT_SLOP: f32 = 1e-3;
+const UNDO_LIMIT: usize = 50;
+const MIN_MASS: f32 = 0.1;
+// Distances to the attractor are clamped to this so particles at the cursor don't fly off.
+const ATTRACTOR_MIN_DISTANCE: f32 = 20.0;
+// Air drag `Cloth::settle` applies at the least, enough to take most of the motion out in a
+// second of simulated time.
+const SETTLE_DRAG: f32 = 0.1;
+// Steps in a row the cloth has to stay calm for `Cloth::settle`, a quarter of a second, so a
+// swing that only pauses at the end of its arc isn't taken for rest.
+const SETTLE_CALM_STEPS: usize = 30;
+// How far `Cloth::reinforce` can strengthen a spring.
+pub const MAX_SPRING_STRENGTH: f32 = 5.0;
+
+#[derive(Clone, Copy)]
+pub struct Particle {
+    pub pos: Vec2,
+    pub old_pos: Vec2,
+    pub acceleration: Vec2,
+    pub mass: f32,
+    pub is_pinned: bool,
+    // Temperature from 0 (cold) to 1. Hot particles weaken the springs around them.
+    pub heat: f32,
+    // Fills a grid cell that a shaped cloth leaves out. It's pinned, has no springs, and is
+    // skipped by collisions, picking and drawing, so the grid keeps its layout around holes.
+    pub absent: bool
+}
+
+impl Particle {
+    pub fn new(x: f32, y: f32) -> Self {
+        Particle {
+            pos: vec2(x, y),
+            old_pos: vec2(x, y),
+            acceleration: Vec2::ZERO,
+            mass: 1.0,
+            is_pinned: false,
+            heat: 0.0,
+            absent: false
+        }
+    }
+
+    // `max_step` caps how far the particle may move in this step, keeping its direction.
+    pub fn update(&mut self, dt: f32, drag: f32, max_step: Option<f32>) {
+        if self.is_pinned {
+            // Pinned particles still receive forces; drop them so unpinning later doesn't lurch.
+            self.acceleration = Vec2::ZERO;
+            return;
+        }
+
+        let velocity = self.pos - self.old_pos;
+        if drag > 0.0 {
+            // `velocity` is the displacement over the last step, so divide by dt to get the real
+            // velocity. The coefficient is the fraction of velocity lost per 1/60 s, which keeps
+            // the damping the same at any frame rate.
+            self.apply_force(-velocity / dt * drag * 60.0 * self.mass);
+        }
+        let mut step = velocity + self.acceleration * dt * dt;
+        if let Some(max_step) = max_step {
+            step = step.clamp_length_max(max_step);
+        }
+        self.old_pos = self.pos;
+        self.pos += step;
+        self.acceleration = Vec2::ZERO;
+    }
+
+    pub fn apply_force(&mut self, force: Vec2) {
+        self.acceleration += force / self.mass;
+    }
+
+    // Mass divides every applied force, so it must never reach zero.
+    pub fn set_mass(&mut self, mass: f32) {
+        self.mass = mass.max(MIN_MASS);
+    }
+
+    pub fn toggle_pin(&mut self) {
+        if self.absent {
+            return;
+        }
+        self.is_pinned = !self.is_pinned;
+        self.old_pos = self.pos;
+    }
+
+    // Puts the particle at `contact` on a surface facing along `normal` and splits its velocity
+    // there: the part heading into the surface bounces back scaled by the restitution, and the
+    // part along it loses the friction fraction. Restitution 0 with friction 1 leaves the
+    // particle resting where it landed.
+    fn respond(&mut self, contact: Vec2, normal: Vec2, collision: &CollisionParams) {
+        let velocity = self.pos - self.old_pos;
+        let normal_speed = velocity.dot(normal);
+        let tangential = velocity - normal * normal_speed;
+        let bounced = if normal_speed < 0.0 { -normal_speed * collision.restitution } else { normal_speed };
+        self.pos = contact;
+        self.old_pos = contact - (tangential * (1.0 - collision.friction) + normal * bounced);
+    }
+
+    pub fn collide_floor(&mut self, floor_y: f32, collision: &CollisionParams) {
+        if self.is_pinned || self.pos.y < floor_y {
+            return;
+        }
+        self.respond(vec2(self.pos.x, floor_y), Vec2::NEG_Y, collision);
+    }
+
+    // Keeps the particle inside `bounds`, responding to each wall it crossed in turn.
+    pub fn collide_walls(&mut self, bounds: &RectObstacle, collision: &CollisionParams) {
+        if self.is_pinned {
+            return;
+        }
+        let clamped = self.pos.clamp(bounds.min, bounds.max);
+        if clamped.x != self.pos.x {
+            let normal = if self.pos.x < bounds.min.x { Vec2::X } else { Vec2::NEG_X };
+            self.respond(vec2(clamped.x, self.pos.y), normal, collision);
+        }
+        if clamped.y != self.pos.y {
+            let normal = if self.pos.y < bounds.min.y { Vec2::Y } else { Vec2::NEG_Y };
+            self.respond(clamped, normal, collision);
+        }
+    }
+
+    // Where the particle leaves a circle it is within `slop` of being inside, and the surface
+    // normal there.
+    fn circle_contact(&self, obstacle: &CircleObstacle, slop: f32) -> Option<(Vec2, Vec2)> {
+        let offset = self.pos - obstacle.center;
+        let radius = obstacle.radius + slop;
+        if self.is_pinned || offset.length_squared() >= radius * radius {
+            return None;
+        }
+        // A particle sitting exactly on the center has no direction to escape in, so push it up.
+        let normal = offset.try_normalize().unwrap_or(Vec2::NEG_Y);
+        Some((obstacle.center + normal * obstacle.radius, normal))
+    }
+
+    // Like `circle_contact`, through the face the particle is least deep behind, which also
+    // settles which way a particle near a corner escapes.
+    fn rect_contact(&self, obstacle: &RectObstacle, slop: f32) -> Option<(Vec2, Vec2)> {
+        let inside = self.pos.cmpgt(obstacle.min - slop).all() && self.pos.cmplt(obstacle.max + slop).all();
+        if self.is_pinned || !inside {
+            return None;
+        }
+        let faces = [
+            (self.pos.x - obstacle.min.x, Vec2::NEG_X),
+            (obstacle.max.x - self.pos.x, Vec2::X),
+            (self.pos.y - obstacle.min.y, Vec2::NEG_Y),
+            (obstacle.max.y - self.pos.y, Vec2::Y)
+        ];
+        let (depth, normal) = faces.into_iter().min_by(|a, b| a.0.total_cmp(&b.0)).unwrap();
+        Some((self.pos + normal * depth, normal))
+    }
+
+    // Moves the particle out of the obstacle without touching its velocity, for the
+    // constraint iterations. `collide_circle` applies the response once the substep is solved.
+    pub fn push_out_of_circle(&mut self, obstacle: &CircleObstacle) {
+        if let Some((contact, _)) = self.circle_contact(obstacle, 0.0) {
+            self.pos = contact;
+        }
+    }
+
+    pub fn push_out_of_rect(&mut self, obstacle: &RectObstacle) {
+        if let Some((contact, _)) = self.rect_contact(obstacle, 0.0) {
+            self.pos = contact;
+        }
+    }
+
+    // Pushes out and responds with restitution and friction. A particle the iterations already
+    // left on the surface still counts as touching it.
+    pub fn collide_circle(&mut self, obstacle: &CircleObstacle, collision: &CollisionParams) {
+        if let Some((contact, normal)) = self.circle_contact(obstacle, CONTACT_SLOP) {
+            self.respond(contact, normal, collision);
+        }
+    }
+
+    pub fn collide_rect(&mut self, obstacle: &RectObstacle, collision: &CollisionParams) {
+        if let Some((contact, normal)) = self.rect_contact(obstacle, CONTACT_SLOP) {
+            self.respond(contact, normal, collision);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SpringKind {
+    Structural,
+    Shear,
+    Bend
+}
+
+#[derive(Clone, Copy)]
+pub struct Spring {
+    pub p1_idx: usize,
+    pub p2_idx: usize,
+    pub rest_length: f32,
+    pub kind: SpringKind,
+    // 1 for an ordinary spring. A reinforced spring acts like this many springs laid on top of
+    // each other: it corrects its error as if solved that many times per iteration, and
+    // stretches that many times as far before it tears.
+    pub strength: f32
+}
+
+impl Spring {
+    pub fn new(p1_idx: usize, p2_idx: usize, rest_length: f32, kind: SpringKind) -> Self {
+        Spring { p1_idx, p2_idx, rest_length, kind, strength: 1.0 }
+    }
+}
+
+// Resists bending at `middle` between its two neighbours `a` and `b` along a grid row or column,
+// by pulling it back onto the straight line between them. A bend spring across the pair only
+// sees how far apart the ends are; this measures the curvature itself, so stretching the row
+// doesn't change how hard it resists bending.
+#[derive(Clone, Copy)]
+pub struct BendConstraint {
+    pub a: usize,
+    pub middle: usize,
+    pub b: usize
+}
+
+pub struct Wind {
+    pub base: Vec2,
+    pub gust_amplitude: f32,
+    pub gust_frequency: f32
+}
+
+impl Wind {
+    pub fn new(base: Vec2, gust_amplitude: f32, gust_frequency: f32) -> Self {
+        Wind { base, gust_amplitude, gust_frequency }
+    }
+
+    // Gusts blow along the base direction, so a calm base wind means no gusts either.
+    pub fn force_at(&self, time: f32) -> Vec2 {
+        let gust = (time * self.gust_frequency * std::f32::consts::TAU).sin() * self.gust_amplitude;
+        self.base + self.base.normalize_or_zero() * gust
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathShape {
+    HorizontalSine,
+    VerticalSine,
+    Circle
+}
+
+impl PathShape {
+    pub const ALL: [PathShape; 3] = [PathShape::HorizontalSine, PathShape::VerticalSine, PathShape::Circle];
+    pub const LABELS: [&'static str; 3] = ["Horizontal sine", "Vertical sine", "Circle"];
+}
+
+// A repeating path for driving a pinned particle, such as the top of a pole waving a flag.
+// Every shape starts at `origin` and comes back to it once per cycle.
+#[derive(Clone, Copy)]
+pub struct MotionPath {
+    pub shape: PathShape,
+    pub origin: Vec2,
+    pub amplitude: f32,
+    pub frequency: f32
+}
+
+impl MotionPath {
+    // `time` is in seconds since the path started.
+    pub fn position_at(&self, time: f32) -> Vec2 {
+        let angle = time * self.frequency * std::f32::consts::TAU;
+        let offset = match self.shape {
+            PathShape::HorizontalSine => vec2(angle.sin(), 0.0),
+            PathShape::VerticalSine => vec2(0.0, angle.sin()),
+            // Around a center one radius to the left, so it starts on the origin.
+            PathShape::Circle => vec2(angle.cos() - 1.0, angle.sin())
+        };
+        self.origin + offset * self.amplitude
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedParticle {
+    pos: [f32; 2],
+    old_pos: [f32; 2],
+    mass: f32,
+    is_pinned: bool,
+    #[serde(default)]
+    absent: bool
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedSpring {
+    p1_idx: usize,
+    p2_idx: usize,
+    rest_length: f32,
+    kind: SpringKind,
+    // Older saves have no reinforced springs.
+    #[serde(default = "default_strength")]
+    strength: f32
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedCloth {
+    width: usize,
+    height: usize,
+    // Older saves have a single `spacing` for both axes.
+    #[serde(alias = "spacing")]
+    spacing_x: f32,
+    #[serde(default)]
+    spacing_y: Option<f32>,
+    particles: Vec<SavedParticle>,
+    springs: Vec<SavedSpring>
+}
+
+#[derive(Debug)]
+pub enum ClothFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    ParticleCount { expected: usize, found: usize },
+    SpringOutOfBounds { spring: usize, particle_count: usize },
+    SizeOverflow { width: usize, height: usize },
+    InvalidSpacing { x: f32, y: f32 }
+}
+
+impl fmt::Display for ClothFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClothFileError::Io(e) => write!(f, "I/O error: {}", e),
+            ClothFileError::Json(e) => write!(f, "invalid cloth file: {}", e),
+            ClothFileError::ParticleCount { expected, found } => {
+                write!(f, "expected at least {} particles for the saved size but found {}", expected, found)
+            }
+            ClothFileError::SpringOutOfBounds { spring, particle_count } => {
+                write!(f, "spring {} references a particle outside 0..{}", spring, particle_count)
+            }
+            ClothFileError::SizeOverflow { width, height } => {
+                write!(f, "saved size {}x{} is too large", width, height)
+            }
+            ClothFileError::InvalidSpacing { x, y } => {
+                write!(f, "spacing {}x{} must be positive and finite", x, y)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClothFileError {}
+
+// A cloth `Cloth::try_new` refused to build, with its estimated footprint and the cap, in bytes.
+#[derive(Debug)]
+pub struct ClothSizeError {
+    pub footprint: usize,
+    pub limit: usize
+}
+
+impl fmt::Display for ClothSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mb = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+        write!(f, "the cloth would take about {:.1} MB, over the {:.1} MB limit", mb(self.footprint), mb(self.limit))
+    }
+}
+
+impl std::error::Error for ClothSizeError {}
+
+impl From<std::io::Error> for ClothFileError {
+    fn from(e: std::io::Error) -> Self {
+        ClothFileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ClothFileError {
+    fn from(e: serde_json::Error) -> Self {
+        ClothFileError::Json(e)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PinMode {
+    TopRow,
+    TopCorners,
+    AllCorners,
+    LeftEdge,
+    None
+}
+
+impl PinMode {
+    pub const ALL: [PinMode; 5] = [PinMode::TopRow, PinMode::TopCorners, PinMode::AllCorners, PinMode::LeftEdge, PinMode::None];
+    pub const LABELS: [&'static str; 5] = ["Top row", "Top corners", "All corners", "Left edge", "None"];
+
+    pub fn is_pinned(self, x: usize, y: usize, width: usize, height: usize) -> bool {
+        let corner_x = x == 0 || x == width - 1;
+        match self {
+            PinMode::TopRow => y == 0,
+            PinMode::TopCorners => y == 0 && corner_x,
+            PinMode::AllCorners => (y == 0 || y == height - 1) && corner_x,
+            PinMode::LeftEdge => x == 0,
+            PinMode::None => false
+        }
+    }
+}
+
+// How the grid is laid out from `ClothConfig::start`. The pin mode is applied in grid terms, so
+// a sideways sheet pinned along its top row hangs from a side edge instead. Turning the grid a
+// quarter either way keeps the corners of every quad in clockwise order.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Orientation {
+    // Rows run across the screen and columns down it.
+    Hanging,
+    // Turned so the top row runs up the left side and the sheet reaches out to the right.
+    FromLeft,
+    // Turned so the top row runs down the right side and the sheet reaches out to the left.
+    FromRight
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 3] = [Orientation::Hanging, Orientation::FromLeft, Orientation::FromRight];
+    pub const LABELS: [&'static str; 3] = ["Hanging", "From left edge", "From right edge"];
+
+    // Where the particle at grid offset `offset` sits, relative to the start, in a grid whose
+    // far corner is at offset `extent`.
+    pub fn place(self, offset: Vec2, extent: Vec2) -> Vec2 {
+        match self {
+            Orientation::Hanging => offset,
+            Orientation::FromLeft => vec2(offset.y, extent.x - offset.x),
+            Orientation::FromRight => vec2(extent.y - offset.y, offset.x)
+        }
+    }
+}
+
+// Small xorshift generator so seeded cloths come out identical on every run and platform.
+struct XorShift64 {
+    state: u64
+}
+
+impl XorShift64 {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero, so mix the seed into a non-zero starting state.
+        XorShift64 { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // Uniform in [-1, 1).
+    fn next_signed(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct ClothConfig {
+    pub width: usize,
+    pub height: usize,
+    // Rest distance between neighbouring particles across a row and down a column.
+    pub spacing_x: f32,
+    pub spacing_y: f32,
+    pub start: Vec2,
+    pub pin_mode: PinMode,
+    pub orientation: Orientation,
+    // Maximum random offset of each free particle, drawn from `seed`.
+    pub jitter: f32,
+    pub seed: u64,
+    pub shape: ClothShape,
+    pub diagonals: DiagonalMode,
+    pub layers: SpringLayers
+}
+
+impl ClothConfig {
+    pub fn new(width: usize, height: usize, spacing: f32, start: Vec2) -> Self {
+        ClothConfig {
+            width,
+            height,
+            spacing_x: spacing,
+            spacing_y: spacing,
+            start,
+            pin_mode: PinMode::TopRow,
+            orientation: Orientation::Hanging,
+            jitter: 0.0,
+            seed: 0,
+            shape: ClothShape::Grid,
+            diagonals: DiagonalMode::Both,
+            layers: SpringLayers::ALL
+        }
+    }
+
+    // About how many bytes `Cloth::new` would take for the particles and springs of this
+    // config, counted without building it, so oversized cloths can be refused up front. A size
+    // too large to count saturates at `usize::MAX`.
+    pub fn estimated_footprint(&self) -> usize {
+        self.checked_footprint().unwrap_or(usize::MAX)
+    }
+
+    // `estimated_footprint`, or None if counting the particles or springs overflows.
+    fn checked_footprint(&self) -> Option<usize> {
+        let (w, h) = (self.width.max(2), self.height.max(2));
+        let (particles, springs) = match self.shape {
+            ClothShape::Rope => (w, w - 1),
+            // A disk leaves cells out, so the full grid is an upper bound for it.
+            ClothShape::Grid | ClothShape::Disc => {
+                let diagonals = match self.diagonals {
+                    DiagonalMode::Both => 2,
+                    DiagonalMode::Single => 1,
+                    DiagonalMode::None => 0
+                };
+                let structural = (w - 1).checked_mul(h)?.checked_add(w.checked_mul(h - 1)?)?;
+                let shear = (w - 1).checked_mul(h - 1)?.checked_mul(diagonals)?;
+                let bend = (w - 2).checked_mul(h)?.checked_add(w.checked_mul(h - 2)?)?;
+                let layers = [(self.layers.structural, structural), (self.layers.shear, shear), (self.layers.bend, bend)];
+                let springs = layers.into_iter()
+                    .filter_map(|(included, count)| included.then_some(count))
+                    .try_fold(0usize, |total, count| total.checked_add(count))?;
+                (w.checked_mul(h)?, springs)
+            }
+        };
+        particles.checked_mul(std::mem::size_of::<Particle>())?
+            .checked_add(springs.checked_mul(std::mem::size_of::<Spring>())?)
+    }
+}
+
+fn footprint(particles: usize, springs: usize) -> usize {
+    particles.saturating_mul(std::mem::size_of::<Particle>())
+        .saturating_add(springs.saturating_mul(std::mem::size_of::<Spring>()))
+}
+
+// Which kinds of spring a grid cloth is built with.
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpringLayers {
+    pub structural: bool,
+    pub shear: bool,
+    pub bend: bool
+}
+
+impl SpringLayers {
+    pub const ALL: SpringLayers = SpringLayers { structural: true, shear: true, bend: true };
+
+    pub fn includes(self, kind: SpringKind) -> bool {
+        match kind {
+            SpringKind::Structural => self.structural,
+            SpringKind::Shear => self.shear,
+            SpringKind::Bend => self.bend
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ClothShape {
+    Grid,
+    // A single horizontal chain of `width` particles; `height` is ignored.
+    Rope,
+    // The ellipse that fits in the `width` by `height` grid, with the cells outside it absent.
+    Disc
+}
+
+impl ClothShape {
+    pub const ALL: [ClothShape; 3] = [ClothShape::Grid, ClothShape::Rope, ClothShape::Disc];
+    pub const LABELS: [&'static str; 3] = ["Grid", "Rope", "Disc"];
+}
+
+// Which shear springs each grid quad gets. Both diagonals brace it evenly; a single one, always
+// from the top-left to the bottom-right corner, resists shear one way much more than the other.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DiagonalMode {
+    Both,
+    Single,
+    None
+}
+
+impl DiagonalMode {
+    pub const ALL: [DiagonalMode; 3] = [DiagonalMode::Both, DiagonalMode::Single, DiagonalMode::None];
+    pub const LABELS: [&'static str; 3] = ["Both diagonals", "One diagonal", "No diagonals"];
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SolveMode {
+    // Springs correct a stiffness-scaled share of their error per iteration and stretch.
+    Elastic,
+    // Springs are projected straight back to their rest length every iteration.
+    Inextensible
+}
+
+impl SolveMode {
+    pub const ALL: [SolveMode; 2] = [SolveMode::Elastic, SolveMode::Inextensible];
+    pub const LABELS: [&'static str; 2] = ["Elastic", "Inextensible"];
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SolveOrder {
+    // Every kind of spring in every iteration. This is the default.
+    Interleaved,
+    // Structural and shear springs for all the iterations, then a single pass of bend springs,
+    // so the long-range springs can't fight the weave and make it buzz at high stiffness.
+    StructuralFirst
+}
+
+impl SolveOrder {
+    pub const ALL: [SolveOrder; 2] = [SolveOrder::Interleaved, SolveOrder::StructuralFirst];
+    pub const LABELS: [&'static str; 2] = ["Interleaved", "Structural first"];
+}
+
+// Buckets positions into square cells so neighborhood queries only look at nearby points.
+// Indices refer to the order of the positions passed to `rebuild`.
+pub struct SpatialHash {
+    pub cell_size: f32,
+    pub cells: HashMap<(i32, i32), Vec<usize>>,
+    pub positions: Vec<Vec2>,
+    pub min_cell: (i32, i32),
+    pub max_cell: (i32, i32)
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialHash { cell_size, cells: HashMap::new(), positions: Vec::new(), min_cell: (0, 0), max_cell: (0, 0) }
+    }
+
+    pub fn cell_of(&self, pos: Vec2) -> (i32, i32) {
+        ((pos.x / self.cell_size).floor() as i32, (pos.y / self.cell_size).floor() as i32)
+    }
+
+    pub fn rebuild(&mut self, positions: impl IntoIterator<Item = Vec2>) {
+        for bucket in self.cells.values_mut() {
+            bucket.clear();
+        }
+        self.positions.clear();
+        self.positions.extend(positions);
+        self.min_cell = (i32::MAX, i32::MAX);
+        self.max_cell = (i32::MIN, i32::MIN);
+
+        for (i, &pos) in self.positions.iter().enumerate() {
+            let cell = self.cell_of(pos);
+            self.cells.entry(cell).or_default().push(i);
+            self.min_cell = (self.min_cell.0.min(cell.0), self.min_cell.1.min(cell.1));
+            self.max_cell = (self.max_cell.0.max(cell.0), self.max_cell.1.max(cell.1));
+        }
+        // Buckets still in use keep their allocation; the ones the cloth has moved away from
+        // are dropped so a drifting cloth doesn't grow the map forever.
+        self.cells.retain(|_, bucket| !bucket.is_empty());
+    }
+
+    pub fn query_radius(&self, pos: Vec2, radius: f32) -> Vec<usize> {
+        let (min_x, min_y) = self.cell_of(pos - Vec2::splat(radius));
+        let (max_x, max_y) = self.cell_of(pos + Vec2::splat(radius));
+        let mut found = Vec::new();
+        for cx in min_x..=max_x {
+            for cy in min_y..=max_y {
+                if let Some(bucket) = self.cells.get(&(cx, cy)) {
+                    found.extend(bucket.iter().filter(|&&i| self.positions[i].distance_squared(pos) <= radius * radius));
+                }
+            }
+        }
+        found
+    }
+
+    // Searches rings of cells outwards from `pos`. Points in ring `r` are at least `(r - 1)`
+    // cells away, so the search stops once that is further than the best match so far.
+    pub fn query_nearest(&self, pos: Vec2) -> Option<usize> {
+        if self.positions.is_empty() {
+            return None;
+        }
+
+        let (cx, cy) = self.cell_of(pos);
+        let max_ring = (cx - self.min_cell.0).abs()
+            .max((self.max_cell.0 - cx).abs())
+            .max((cy - self.min_cell.1).abs())
+            .max((self.max_cell.1 - cy).abs());
+
+        let mut best: Option<(usize, f32)> = None;
+        for ring in 0..=max_ring {
+            if let Some((_, best_dist_sq)) = best {
+                let ring_dist = (ring - 1) as f32 * self.cell_size;
+                if ring_dist * ring_dist > best_dist_sq {
+                    break;
+                }
+            }
+            for dx in -ring..=ring {
+                for dy in -ring..=ring {
+                    if dx.abs() != ring && dy.abs() != ring {
+                        continue;
+                    }
+                    let Some(bucket) = self.cells.get(&(cx + dx, cy + dy)) else { continue };
+                    for &i in bucket {
+                        let dist_sq = self.positions[i].distance_squared(pos);
+                        if best.is_none_or(|(_, d)| dist_sq < d) {
+                            best = Some((i, dist_sq));
+                        }
+                    }
+                }
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+}
+
+// How particles come off the floor, walls and obstacles. Restitution is the fraction of the
+// speed into a surface they bounce back with, and friction the fraction of the speed along it
+// they lose on contact.
+#[derive(Clone, Copy, PartialEq)]
+pub struct CollisionParams {
+    pub restitution: f32,
+    pub friction: f32
+}
+
+impl Default for CollisionParams {
+    fn default() -> Self {
+        CollisionParams { restitution: 0.0, friction: 0.3 }
+    }
+}
+
+pub struct CircleObstacle {
+    pub center: Vec2,
+    pub radius: f32
+}
+
+#[derive(Clone, Copy)]
+pub struct RectObstacle {
+    pub min: Vec2,
+    pub max: Vec2
+}
+
+impl RectObstacle {
+    // Accepts any two opposite corners.
+    pub fn from_corners(a: Vec2, b: Vec2) -> Self {
+        RectObstacle { min: a.min(b), max: a.max(b) }
+    }
+}
+
+// A hard grab that moves towards `target` a little every substep. The brush particles move
+// along by their weight of the same step. All of them are left at rest after each step, so the
+// drag itself never turns into velocity.
+#[derive(Clone, Copy)]
+pub struct HeldParticle<'a> {
+    pub idx: usize,
+    pub target: Vec2,
+    pub max_step: f32,
+    pub brush: &'a [(usize, f32)]
+}
+
+#[derive(Clone, Copy)]
+pub struct SimParams<'a> {
+    // Constraint passes per substep. More of them pull the springs closer to their rest lengths
+    // within a step, but can't make up for a step too long for how fast the cloth moves.
+    pub iterations: usize,
+    // How many shorter steps each step is split into, each integrating and then running all the
+    // constraint iterations. Shorter steps keep stiff or fast-moving cloth stable far better
+    // than extra iterations do, at the cost of redoing the whole step N times. 0 counts as 1.
+    pub substeps: usize,
+    // With a tolerance, constraint iterations stop as soon as no spring is stretched or
+    // compressed by more than that fraction of its rest length, and `iterations` is the cap.
+    pub strain_tolerance: Option<f32>,
+    pub gravity: Vec2,
+    pub wind: Vec2,
+    pub stiffness: f32,
+    pub bend_stiffness: f32,
+    // Fraction of each bend constraint's curvature removed per iteration, when they're solved.
+    // They work alongside the bend springs, or replace them in a cloth built without those.
+    pub bend_constraint_stiffness: Option<f32>,
+    pub tear_threshold: f32,
+    pub structural_tear_scale: f32,
+    pub shear_tear_scale: f32,
+    pub bend_tear_scale: f32,
+    // With a limit, springs tear once stretched that far past their rest length in world units,
+    // however long they are, instead of at `tear_threshold` times their rest length. The
+    // threshold and the per-kind scales are ignored then.
+    pub tear_distance: Option<f32>,
+    // With a weight, springs also tear from being stretched quickly: how fast a spring is
+    // lengthening, in world units per second, times this weight in seconds counts towards its
+    // stretch. A slow pull can draw the cloth far out, while a sharp yank rips it.
+    pub tear_rate_weight: Option<f32>,
+    pub rest_length_scale: f32,
+    // Fraction of a spring's stiffness and of its stretch before tearing that is lost at full
+    // heat. Heat spreads along structural springs at `heat_diffusion` per second and decays
+    // by `heat_decay` per second.
+    pub heat_weakening: f32,
+    pub heat_diffusion: f32,
+    pub heat_decay: f32,
+    // Fraction of a spring's error each endpoint corrects per iteration. 0.5 splits it exactly;
+    // larger values over-relax and converge in fewer iterations, but near 1.0 they overshoot
+    // and can blow up at high stiffness.
+    pub relaxation: f32,
+    pub solve_mode: SolveMode,
+    pub solve_order: SolveOrder,
+    pub drag: f32,
+    // Fraction of the speed at which each spring lengthens or shortens that is taken out once
+    // per substep, like a dashpot alongside it. Unlike drag it leaves the cloth's motion as a
+    // whole alone and only kills the buzzing of stiff springs against each other.
+    pub spring_damping: f32,
+    pub max_step: Option<f32>,
+    pub max_stretch: Option<f32>,
+    pub floor_y: Option<f32>,
+    // A box the cloth can't leave.
+    pub walls: Option<RectObstacle>,
+    // The response to every surface: the floor, the walls and the obstacles.
+    pub collision: CollisionParams,
+    pub aerodynamic_wind: bool,
+    // Outward push of the gas inside each intact quad, per unit of edge length.
+    pub pressure: f32,
+    // A particle pulled towards a target by a critically damped spring of `grab_stiffness`, for
+    // dragging it gently instead of moving it outright. The stiffness is per unit of mass, so
+    // heavy particles follow just as closely. Above about 50000 the spring overshoots at the
+    // fixed time step.
+    pub grab: Option<(usize, Vec2)>,
+    pub grab_stiffness: f32,
+    // A particle dragged outright, but by at most `max_step` per substep so it can't skip past
+    // an obstacle between two collision checks, see `HeldParticle`.
+    pub held: Option<HeldParticle<'a>>,
+    // Position and strength of the mouse attractor, when it's on.
+    pub attractor: Option<(Vec2, f32)>,
+    // Solve every spring from the same start-of-pass positions instead of one after another.
+    // Takes precedence over `parallel`.
+    pub jacobi: bool,
+    pub parallel: bool,
+    pub self_collision: Option<f32>,
+    // Minimum distance between particles of different cloths. Only `step_scene` uses it.
+    pub cloth_collision: Option<f32>,
+    // Obstacles belong to the scene, so every cloth collides against the same set.
+    pub obstacles: &'a [CircleObstacle],
+    pub rect_obstacles: &'a [RectObstacle]
+}
+
+impl Default for SimParams<'_> {
+    fn default() -> Self {
+        SimParams {
+            iterations: 5,
+            substeps: 1,
+            strain_tolerance: None,
+            gravity: vec2(0.0, 980.0),
+            wind: Vec2::ZERO,
+            stiffness: 0.9,
+            bend_stiffness: 0.9,
+            bend_constraint_stiffness: None,
+            tear_threshold: 4.5,
+            structural_tear_scale: 1.0,
+            shear_tear_scale: 1.0,
+            bend_tear_scale: 1.0,
+            tear_distance: None,
+            tear_rate_weight: None,
+            rest_length_scale: 1.0,
+            heat_weakening: 1.0,
+            heat_diffusion: 2.0,
+            heat_decay: 0.5,
+            relaxation: 0.5,
+            solve_mode: SolveMode::Elastic,
+            solve_order: SolveOrder::Interleaved,
+            drag: 0.0,
+            spring_damping: 0.0,
+            max_step: None,
+            max_stretch: None,
+            floor_y: None,
+            walls: None,
+            collision: CollisionParams::default(),
+            aerodynamic_wind: false,
+            pressure: 0.0,
+            grab: None,
+            grab_stiffness: 5000.0,
+            held: None,
+            attractor: None,
+            jacobi: false,
+            parallel: false,
+            self_collision: None,
+            cloth_collision: None,
+            obstacles: &[],
+            rect_obstacles: &[]
+        }
+    }
+}
+
+impl SimParams<'_> {
+    // Springs keep their construction length; the scale is applied on top so it never drifts.
+    // Each endpoint moves at most half the error, so even a strongly shrunk cloth crumples
+    // inwards instead of overshooting.
+    pub fn rest_length(&self, spring: &Spring) -> f32 {
+        spring.rest_length * self.rest_length_scale
+    }
+
+    pub fn stiffness_for(&self, kind: SpringKind) -> f32 {
+        match kind {
+            SpringKind::Structural | SpringKind::Shear => self.stiffness,
+            SpringKind::Bend => self.bend_stiffness
+        }
+    }
+
+    // Never lets a spring tear at or below its rest length, whatever the multiplier.
+    pub fn tear_threshold_for(&self, kind: SpringKind) -> f32 {
+        let scale = match kind {
+            SpringKind::Structural => self.structural_tear_scale,
+            SpringKind::Shear => self.shear_tear_scale,
+            SpringKind::Bend => self.bend_tear_scale
+        };
+        (self.tear_threshold * scale).max(1.1)
+    }
+
+    // The kinds of spring solved in every constraint iteration.
+    pub fn iterated_kinds(&self) -> SpringLayers {
+        match self.solve_order {
+            SolveOrder::Interleaved => SpringLayers::ALL,
+            SolveOrder::StructuralFirst => SpringLayers { bend: false, ..SpringLayers::ALL }
+        }
+    }
+
+    // How much of its strength a spring keeps at the average `heat` of its endpoints.
+    pub fn heat_factor(&self, heat: f32) -> f32 {
+        1.0 - self.heat_weakening * heat.clamp(0.0, 1.0)
+    }
+}
+
+// An in-memory copy of a cloth's particles and springs, for quick checkpoints.
+pub struct ClothState {
+    particles: Vec<Particle>,
+    springs: Vec<Spring>,
+    width: usize,
+    height: usize,
+    spacing: Vec2,
+    initial_positions: Vec<Vec2>,
+    initial_springs: Vec<Spring>
+}
+
+// What one frame cut, tore or split, for undo. Each split is a (copy, original) pair of
+// particle indices.
+struct Removal {
+    springs: Vec<Spring>,
+    splits: Vec<(usize, usize)>
+}
+
+pub struct Cloth {
+    particles: Vec<Particle>,
+    springs: Vec<Spring>,
+    width: usize,
+    height: usize,
+    spacing: Vec2,
+    // Springs grouped so that no two in a group share a particle. Rebuilt lazily after the
+    // spring list changes.
+    spring_colors: Option<Vec<Vec<usize>>>,
+    // Particle pairs joined by a spring, so self-collision leaves the weave alone.
+    spring_pairs: Option<HashSet<(usize, usize)>>,
+    // Every grid triple whose two structural edges are intact, also rebuilt lazily.
+    bend_constraints: Option<Vec<BendConstraint>>,
+    collision_hash: SpatialHash,
+    // Springs cut or torn and particles split off by hard cuts since the last `end_frame`, and
+    // one entry per frame that did either.
+    removed_springs: Vec<Spring>,
+    split_particles: Vec<(usize, usize)>,
+    undo_stack: VecDeque<Removal>,
+    // Summed corrections and how many springs contributed, per particle, for the Jacobi pass.
+    jacobi_corrections: Vec<(Vec2, u32)>,
+    // Layout the cloth was built or loaded with, for `reset_positions`.
+    initial_positions: Vec<Vec2>,
+    initial_springs: Vec<Spring>,
+    // How many springs each particle had in the initial layout.
+    expected_degree: Vec<u32>,
+    // Length of the last substep, which is how far apart `pos` and `old_pos` are in time.
+    substep_dt: f32
+}
+
+impl Cloth {
+    pub fn new(config: &ClothConfig) -> Self {
+        let ClothConfig { spacing_x, start, .. } = *config;
+        // Anything thinner than two particles has no quads to build, so it's widened to the
+        // smallest sheet that does. Spring families that don't fit, like bend springs across
+        // only two particles, are left out below.
+        let (width, height) = (config.width.max(2), config.height.max(2));
+        match config.shape {
+            ClothShape::Grid => Cloth::new_grid(config, |_, _| true),
+            ClothShape::Rope => Cloth::new_rope(width, spacing_x, start, config.pin_mode != PinMode::None),
+            ClothShape::Disc => Cloth::new_grid(config, |x, y| {
+                // Measured from cell centers, so a row or column on the edge isn't cut off.
+                let half = vec2(width as f32, height as f32) * 0.5;
+                ((vec2(x as f32, y as f32) + 0.5 - half) / half).length_squared() <= 1.0
+            })
+        }
+    }
+
+    // The grid `new` builds, with the cells `present` rejects made absent along with every
+    // spring touching them. A bend spring also needs the particle it skips over, so none
+    // bridge a hole.
+    fn new_grid(config: &ClothConfig, present: impl Fn(usize, usize) -> bool) -> Self {
+        let ClothConfig { spacing_x, spacing_y, start, .. } = *config;
+        let (width, height) = (config.width.max(2), config.height.max(2));
+        let mut rng = XorShift64::new(config.seed);
+        let mut particles = Vec::with_capacity(width * height);
+        let extent = vec2(width.saturating_sub(1) as f32 * spacing_x, height.saturating_sub(1) as f32 * spacing_y);
+        for y in 0..height {
+            for x in 0..width {
+                let offset = vec2(x as f32 * spacing_x, y as f32 * spacing_y);
+                let pos = start + config.orientation.place(offset, extent);
+                let mut p = Particle::new(pos.x, pos.y);
+                p.is_pinned = config.pin_mode.is_pinned(x, y, width, height);
+                if !p.is_pinned && config.jitter > 0.0 {
+                    p.pos += vec2(rng.next_signed(), rng.next_signed()) * config.jitter;
+                    p.old_pos = p.pos;
+                }
+                particles.push(p);
+            }
+        }
+
+        let mut springs = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let current_idx = y * width + x;
+                if x < width - 1 {
+                    springs.push(Spring::new(current_idx, current_idx + 1, spacing_x, SpringKind::Structural));
+                    if x < width - 2 {
+                         springs.push(Spring::new(current_idx, current_idx + 2, spacing_x * 2.0, SpringKind::Bend));
+                    }
+                }
+                if y < height - 1 {
+                    springs.push(Spring::new(current_idx, current_idx + width, spacing_y, SpringKind::Structural));
+                    if y < height - 2 {
+                        springs.push(Spring::new(current_idx, current_idx + (2 * width), spacing_y * 2.0, SpringKind::Bend));
+                    }
+                }
+                if x < width - 1 && y < height - 1 && config.diagonals != DiagonalMode::None {
+                    let diagonal_len = (spacing_x.powi(2) + spacing_y.powi(2)).sqrt();
+                    springs.push(Spring::new(current_idx, current_idx + width + 1, diagonal_len, SpringKind::Shear));
+                    if config.diagonals == DiagonalMode::Both {
+                        springs.push(Spring::new((y * width) + (x + 1), (y + 1) * width + x, diagonal_len, SpringKind::Shear));
+                    }
+                }
+            }
+        }
+
+        springs.retain(|s| config.layers.includes(s.kind));
+
+        let present: Vec<bool> = (0..width * height).map(|i| present(i % width, i / width)).collect();
+        for (p, _) in particles.iter_mut().zip(&present).filter(|(_, &present)| !present) {
+            p.absent = true;
+            p.is_pinned = true;
+        }
+        springs.retain(|s| {
+            present[s.p1_idx] && present[s.p2_idx] && (s.kind != SpringKind::Bend || present[(s.p1_idx + s.p2_idx) / 2])
+        });
+
+        Cloth::from_parts(particles, springs, width, height, vec2(spacing_x, spacing_y))
+    }
+
+    // Like `new`, but refuses a config whose estimated footprint is over `max_bytes` instead of
+    // trying to allocate it. A config too large to even count is always refused.
+    pub fn try_new(config: &ClothConfig, max_bytes: usize) -> Result<Self, ClothSizeError> {
+        match config.checked_footprint() {
+            Some(footprint) if footprint <= max_bytes => Ok(Cloth::new(config)),
+            footprint => Err(ClothSizeError { footprint: footprint.unwrap_or(usize::MAX), limit: max_bytes })
+        }
+    }
+
+    // A chain of `count` particles joined by structural springs only, laid out to the right of
+    // `start`. With `pinned_end` the first particle is pinned, which makes a simple pendulum.
+    pub fn new_rope(count: usize, spacing: f32, start: Vec2, pinned_end: bool) -> Self {
+        let mut particles: Vec<Particle> = (0..count)
+            .map(|i| Particle::new(start.x + i as f32 * spacing, start.y))
+            .collect();
+        if let Some(first) = particles.first_mut() {
+            first.is_pinned = pinned_end;
+        }
+        let springs = (1..count)
+            .map(|i| Spring::new(i - 1, i, spacing, SpringKind::Structural))
+            .collect();
+        Cloth::from_parts(particles, springs, count, 1, vec2(spacing, spacing))
+    }
+
+    // A cloth in any shape, laid out like `new` on a `width` by `height` grid with the cells
+    // `mask` leaves out absent. `mask` runs row by row, and cells past its end count as absent.
+    // Particles keep their grid indices, so quads stay filled wherever all four corners are
+    // present. Nothing is pinned; pin particles through `particles_mut`.
+    pub fn from_mask(mask: &[bool], width: usize, height: usize, spacing: Vec2, start: Vec2) -> Self {
+        let config = ClothConfig { spacing_y: spacing.y, pin_mode: PinMode::None, ..ClothConfig::new(width, height, spacing.x, start) };
+        Cloth::new_grid(&config, |x, y| x < width && y < height && mask.get(y * width + x) == Some(&true))
+    }
+
+    pub fn from_parts(particles: Vec<Particle>, springs: Vec<Spring>, width: usize, height: usize, spacing: Vec2) -> Self {
+        let initial_positions = particles.iter().map(|p| p.pos).collect();
+        let initial_springs = springs.clone();
+        let expected_degree = spring_degrees(&springs, particles.len());
+        Cloth {
+            particles,
+            springs,
+            width,
+            height,
+            spacing,
+            spring_colors: None,
+            spring_pairs: None,
+            bend_constraints: None,
+            collision_hash: SpatialHash::new(spacing.min_element()),
+            removed_springs: Vec::new(),
+            split_particles: Vec::new(),
+            undo_stack: VecDeque::new(),
+            jacobi_corrections: Vec::new(),
+            initial_positions,
+            initial_springs,
+            expected_degree,
+            substep_dt: FIXED_DT
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    // Velocity of particle `idx` over the last substep, in world units per second.
+    pub fn velocity(&self, idx: usize) -> Vec2 {
+        let p = &self.particles[idx];
+        (p.pos - p.old_pos) / self.substep_dt
+    }
+
+    // Particles can be moved, pinned or reweighted in place, but not added or removed, so the
+    // springs always point at valid indices.
+    pub fn particles_mut(&mut self) -> &mut [Particle] {
+        &mut self.particles
+    }
+
+    pub fn springs(&self) -> &[Spring] {
+        &self.springs
+    }
+
+    // Grid dimensions in particles. Particles split off by a hard cut come after the grid.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Rest distance between grid neighbours along x and y.
+    pub fn spacing(&self) -> Vec2 {
+        self.spacing
+    }
+
+    fn springs_changed(&mut self) {
+        self.spring_colors = None;
+        self.spring_pairs = None;
+        self.bend_constraints = None;
+    }
+
+    // One full step of `dt`, as `params.substeps` substeps. `integrate`, `relax` and
+    // `finish_step` are the stages of a substep, exposed so several cloths can be stepped in
+    // lockstep with collisions resolved between them. Returns the most constraint iterations
+    // any substep ran.
+    pub fn update(&mut self, dt: f32, params: &SimParams) -> usize {
+        let substeps = params.substeps.max(1);
+        let dt = dt / substeps as f32;
+        let mut most_iterations = 0;
+        for _ in 0..substeps {
+            self.integrate(dt, params);
+            let mut iterations = 0;
+            while iterations < params.iterations {
+                self.relax(dt, params);
+                iterations += 1;
+                if self.converged(params) {
+                    break;
+                }
+            }
+            self.finish_step(params);
+            most_iterations = most_iterations.max(iterations);
+        }
+        most_iterations
+    }
+
+    // Steps at `FIXED_DT` under heavy drag until no free particle has moved faster than
+    // `tolerance` world units per second for `SETTLE_CALM_STEPS` steps, or `max_steps` run out,
+    // and returns how many steps it took. Pins, obstacles and everything else in `params` apply
+    // as usual, but grabs and the attractor are left out so nothing keeps stirring the cloth.
+    pub fn settle(&mut self, params: &SimParams, max_steps: usize, tolerance: f32) -> usize {
+        let params = SimParams { drag: params.drag.max(SETTLE_DRAG), grab: None, held: None, attractor: None, ..*params };
+        let mut calm_steps = 0;
+        for step in 1..=max_steps {
+            self.update(FIXED_DT, &params);
+            let fastest = (0..self.particles.len())
+                .filter(|&idx| !self.particles[idx].is_pinned)
+                .map(|idx| self.velocity(idx).length())
+                .fold(0.0, f32::max);
+            calm_steps = if fastest < tolerance { calm_steps + 1 } else { 0 };
+            if calm_steps == SETTLE_CALM_STEPS {
+                return step;
+            }
+        }
+        max_steps
+    }
+
+    // What follows the constraint iterations: the bend pass when those are solved last, the
+    // spring damping, the stretch limit, then the obstacle response.
+    pub fn finish_step(&mut self, params: &SimParams) {
+        if params.solve_order == SolveOrder::StructuralFirst {
+            self.solve_kinds(params, SpringLayers { structural: false, shear: false, bend: true });
+        }
+        if params.spring_damping > 0.0 {
+            self.damp_springs(params.spring_damping);
+        }
+        if let Some(max_stretch) = params.max_stretch {
+            self.clamp_stretch(params, max_stretch);
+        }
+        for obstacle in params.obstacles {
+            for p in self.particles.iter_mut() {
+                p.collide_circle(obstacle, &params.collision);
+            }
+        }
+        for obstacle in params.rect_obstacles {
+            for p in self.particles.iter_mut() {
+                p.collide_rect(obstacle, &params.collision);
+            }
+        }
+    }
+
+    // Whether an adaptive solve can stop: every spring is within `strain_tolerance` of its rest
+    // length. Always false without a tolerance, so fixed iteration counts run in full.
+    pub fn converged(&self, params: &SimParams) -> bool {
+        params.strain_tolerance.is_some_and(|tolerance| self.max_strain(params) < tolerance)
+    }
+
+    // The largest stretch or compression of any spring the iterations solve, as a fraction of
+    // its rest length.
+    pub fn max_strain(&self, params: &SimParams) -> f32 {
+        let kinds = params.iterated_kinds();
+        self.springs.iter()
+            .filter(|s| kinds.includes(s.kind))
+            .map(|s| {
+                let rest = params.rest_length(s);
+                (self.particles[s.p1_idx].pos.distance(self.particles[s.p2_idx].pos) - rest).abs() / rest
+            })
+            .fold(0.0, f32::max)
+    }
+
+    // Spreads heat, applies the external forces and moves every particle one step, before any
+    // constraints.
+    pub fn integrate(&mut self, dt: f32, params: &SimParams) {
+        self.substep_dt = dt;
+        self.update_heat(dt, params);
+        for p in self.particles.iter_mut() {
+            p.apply_force(params.gravity);
+        }
+        if let Some((center, strength)) = params.attractor {
+            self.apply_attractor(center, strength);
+        }
+        if params.aerodynamic_wind {
+            self.apply_aerodynamic_wind(params.wind);
+        } else {
+            for p in self.particles.iter_mut() {
+                p.apply_force(params.wind);
+            }
+        }
+        if params.pressure != 0.0 {
+            self.apply_pressure(params.pressure);
+        }
+        if let Some((idx, target)) = params.grab {
+            if let Some(p) = self.particles.get_mut(idx) {
+                let k = params.grab_stiffness;
+                let velocity = (p.pos - p.old_pos) / dt;
+                p.apply_force(((target - p.pos) * k - velocity * 2.0 * k.sqrt()) * p.mass);
+            }
+        }
+
+        for p in self.particles.iter_mut() {
+            p.update(dt, params.drag, params.max_step);
+        }
+        if let Some(held) = params.held {
+            self.move_held(&held);
+        }
+
+        // Clamp after integrating so a particle that moved past the floor in a single step
+        // still ends up on it instead of tunneling through.
+        if let Some(floor_y) = params.floor_y {
+            for p in self.particles.iter_mut() {
+                p.collide_floor(floor_y, &params.collision);
+            }
+        }
+        if let Some(walls) = &params.walls {
+            for p in self.particles.iter_mut() {
+                p.collide_walls(walls, &params.collision);
+            }
+        }
+    }
+
+    // Runs before the collisions of the substep, so whatever the step pushes into an obstacle
+    // is pushed back out before the next one.
+    fn move_held(&mut self, held: &HeldParticle) {
+        let Some(p) = self.particles.get(held.idx) else { return };
+        let step = (held.target - p.pos).clamp_length_max(held.max_step);
+        for (idx, weight) in std::iter::once((held.idx, 1.0)).chain(held.brush.iter().copied()) {
+            if let Some(p) = self.particles.get_mut(idx) {
+                p.pos += step * weight;
+                p.old_pos = p.pos;
+            }
+        }
+    }
+
+    // One constraint iteration: tearing, the springs, then collisions. `dt` is the step being
+    // relaxed, which rate-dependent tearing needs to turn displacements into speeds.
+    pub fn relax(&mut self, dt: f32, params: &SimParams) {
+        let spring_count = self.springs.len();
+        let particles = &self.particles;
+        let removed = &mut self.removed_springs;
+        self.springs.retain(|s| {
+            let p1 = particles[s.p1_idx];
+            let p2 = particles[s.p2_idx];
+            let mut dist = p1.pos.distance(p2.pos);
+            if let Some(weight) = params.tear_rate_weight {
+                // The endpoints' velocities along the spring; only lengthening counts.
+                let direction = (p2.pos - p1.pos).normalize_or_zero();
+                let rate = ((p2.pos - p2.old_pos) - (p1.pos - p1.old_pos)).dot(direction) / dt;
+                dist += rate.max(0.0) * weight;
+            }
+            let rest_length = params.rest_length(s);
+            // How far past its rest length the spring may stretch.
+            let mut max_stretch = params.tear_distance
+                .unwrap_or_else(|| rest_length * (params.tear_threshold_for(s.kind) - 1.0));
+            max_stretch *= s.strength;
+            let heat = (p1.heat + p2.heat) * 0.5;
+            if heat > 0.0 {
+                // Squared so the tear limit gives way faster than the stiffness does; at full
+                // weakening a hot spring tears as soon as it's stretched at all.
+                max_stretch *= params.heat_factor(heat).powi(2);
+            }
+            let intact = dist < rest_length + max_stretch;
+            if !intact {
+                removed.push(*s);
+            }
+            intact
+        });
+        if self.springs.len() != spring_count {
+            self.springs_changed();
+        }
+
+        self.solve_kinds(params, params.iterated_kinds());
+        if let Some(stiffness) = params.bend_constraint_stiffness {
+            self.solve_bend_constraints(stiffness);
+        }
+
+        if let Some(min_dist) = params.self_collision {
+            self.resolve_self_collision(min_dist);
+        }
+
+        // Resolving overlapping obstacles one after another inside every iteration lets the
+        // springs pull the cloth back together between pushes, so it settles instead of jittering.
+        // Only positions move here; the bounce and friction come once per substep, in
+        // `finish_step`, so they don't compound with the iteration count.
+        for obstacle in params.obstacles {
+            for p in self.particles.iter_mut() {
+                p.push_out_of_circle(obstacle);
+            }
+        }
+        for obstacle in params.rect_obstacles {
+            for p in self.particles.iter_mut() {
+                p.push_out_of_rect(obstacle);
+            }
+        }
+    }
+
+    // Spreads heat between particles joined by a structural spring, then cools every particle.
+    // Heat below a small cutoff drops to zero, so a cold cloth skips the work entirely.
+    fn update_heat(&mut self, dt: f32, params: &SimParams) {
+        if self.particles.iter().all(|p| p.heat == 0.0) {
+            return;
+        }
+        // A particle has at most four structural neighbors, so capping each exchange at a
+        // quarter of the difference keeps the diffusion from overshooting.
+        let rate = (params.heat_diffusion * dt).min(0.25);
+        let mut flow = vec![0.0; self.particles.len()];
+        for spring in self.springs.iter().filter(|s| s.kind == SpringKind::Structural) {
+            let exchange = (self.particles[spring.p2_idx].heat - self.particles[spring.p1_idx].heat) * rate;
+            flow[spring.p1_idx] += exchange;
+            flow[spring.p2_idx] -= exchange;
+        }
+        let cooling = (-params.heat_decay * dt).exp();
+        for (p, flow) in self.particles.iter_mut().zip(flow) {
+            p.heat = (p.heat + flow) * cooling;
+            if p.heat < 1e-3 {
+                p.heat = 0.0;
+            }
+        }
+    }
+
+    // Warms particles within `radius` of `center` by up to `amount`, falling off linearly to
+    // zero at the edge. Heat saturates at 1.
+    pub fn apply_heat(&mut self, center: Vec2, radius: f32, amount: f32) {
+        for p in self.particles.iter_mut().filter(|p| !p.absent) {
+            let dist = p.pos.distance(center);
+            if dist < radius {
+                p.heat = (p.heat + amount * (1.0 - dist / radius)).min(1.0);
+            }
+        }
+    }
+
+    // Strengthens every spring passing within `radius` of `center` by `amount`, up to
+    // `MAX_SPRING_STRENGTH`, like sewing a patch over it. Returns how many springs it reached.
+    pub fn reinforce(&mut self, center: Vec2, radius: f32, amount: f32) -> usize {
+        let mut reached = 0;
+        for spring in self.springs.iter_mut() {
+            let (p1, p2) = (self.particles[spring.p1_idx].pos, self.particles[spring.p2_idx].pos);
+            if distance_point_to_segment(center, p1, p2) <= radius {
+                spring.strength = (spring.strength + amount).min(MAX_SPRING_STRENGTH);
+                reached += 1;
+            }
+        }
+        reached
+    }
+
+    // Pulls every particle towards `center` with a force of `strength / distance`.
+    fn apply_attractor(&mut self, center: Vec2, strength: f32) {
+        for p in self.particles.iter_mut() {
+            let offset = center - p.pos;
+            let dist = offset.length();
+            if dist == 0.0 {
+                continue;
+            }
+            p.apply_force(offset / dist * strength / dist.max(ATTRACTOR_MIN_DISTANCE));
+        }
+    }
+
+    // The sim is flat, so a quad catches wind in proportion to how wide it is across the flow:
+    // each edge contributes its length times |normal . wind direction|, which for the whole quad
+    // is its extent perpendicular to the wind, measured against the rest quad's. A rest quad
+    // facing the flow gets exactly its share of the uniform wind, while quads streaming edge-on
+    // along it catch almost none.
+    fn apply_aerodynamic_wind(&mut self, wind: Vec2) {
+        let Some(direction) = wind.try_normalize() else { return };
+        let across = direction.perp();
+
+        let quads: Vec<[usize; 4]> = self.intact_quads().collect();
+        for quad in quads {
+            let (min, max) = quad.iter().fold((f32::MAX, f32::MIN), |(min, max), &i| {
+                let d = self.particles[i].pos.dot(across);
+                (min.min(d), max.max(d))
+            });
+            let exposure = (max - min) / across.abs().dot(self.spacing);
+            for i in quad {
+                self.particles[i].apply_force(wind * exposure * 0.25);
+            }
+        }
+    }
+
+    // Each intact quad pushes every one of its edges outwards with `pressure` times the edge's
+    // length, split between the edge's two ends. Where two intact quads meet the pushes cancel,
+    // so only the boundary of the fabric is driven out and the sheet puffs up like a pillow. Torn
+    // quads hold no gas, so tears let the pressure out.
+    fn apply_pressure(&mut self, pressure: f32) {
+        let quads: Vec<[usize; 4]> = self.intact_quads().collect();
+        for quad in quads {
+            for i in 0..4 {
+                let (a, b) = (quad[i], quad[(i + 1) % 4]);
+                // The corners run clockwise on screen, so this points away from the quad.
+                let edge = self.particles[b].pos - self.particles[a].pos;
+                let force = vec2(edge.y, -edge.x) * pressure * 0.5;
+                self.particles[a].apply_force(force);
+                self.particles[b].apply_force(force);
+            }
+        }
+    }
+
+    // One pass over the springs of the given kinds, with whichever solver is selected.
+    fn solve_kinds(&mut self, params: &SimParams, kinds: SpringLayers) {
+        if params.jacobi {
+            self.solve_springs_jacobi(params, kinds);
+        } else if params.parallel {
+            self.solve_springs_parallel(params, kinds);
+        } else {
+            self.solve_springs(params, kinds);
+        }
+    }
+
+    fn solve_springs(&mut self, params: &SimParams, kinds: SpringLayers) {
+        for spring in self.springs.iter().filter(|s| kinds.includes(s.kind)) {
+            let Some((p1, p2)) = pair_mut(&mut self.particles, spring.p1_idx, spring.p2_idx) else { continue };
+            let Some((c1, c2)) = spring_correction(p1, p2, spring, params) else { continue };
+            p1.pos += c1;
+            p2.pos += c2;
+        }
+    }
+
+    // Pulls the endpoints of any spring longer than `max_stretch` times its rest length back to
+    // that length, whatever the solver managed. Springs whose endpoints are both pinned are left
+    // alone. Springs held below their tear threshold this way can no longer tear by stretching.
+    fn clamp_stretch(&mut self, params: &SimParams, max_stretch: f32) {
+        for spring in &self.springs {
+            let Some((p1, p2)) = pair_mut(&mut self.particles, spring.p1_idx, spring.p2_idx) else { continue };
+            let delta = p2.pos - p1.pos;
+            let dist = delta.length();
+            let max_dist = params.rest_length(spring) * max_stretch;
+            if dist <= max_dist {
+                continue;
+            }
+
+            let excess = delta * ((dist - max_dist) / dist);
+            match (p1.is_pinned, p2.is_pinned) {
+                (true, true) => {}
+                (true, false) => p2.pos -= excess,
+                (false, true) => p1.pos += excess,
+                (false, false) => {
+                    p1.pos += excess * 0.5;
+                    p2.pos -= excess * 0.5;
+                }
+            }
+        }
+    }
+
+    // The bend constraints along every row and column, skipping any that a tear or cut has
+    // broken. Empty for a cloth that has lost its grid.
+    pub fn bend_constraints(&mut self) -> &[BendConstraint] {
+        let (springs, width, height) = (&self.springs, self.width, self.height);
+        self.bend_constraints.get_or_insert_with(|| grid_bend_constraints(springs, width, height))
+    }
+
+    // Moves the middle of each triple towards the midpoint of its ends and the ends the other
+    // way, split by inverse mass so pinned particles stay put, taking `stiffness` of the offset
+    // out each time.
+    fn solve_bend_constraints(&mut self, stiffness: f32) {
+        let (springs, width, height) = (&self.springs, self.width, self.height);
+        let constraints = self.bend_constraints.get_or_insert_with(|| grid_bend_constraints(springs, width, height));
+        let particles = &mut self.particles;
+        let inverse_mass = |p: &Particle| if p.is_pinned { 0.0 } else { 1.0 / p.mass };
+        for c in constraints.iter() {
+            let (a, middle, b) = (particles[c.a], particles[c.middle], particles[c.b]);
+            let (wa, wm, wb) = (inverse_mass(&a), inverse_mass(&middle), inverse_mass(&b));
+            // The middle moves one for one with the offset and each end by a half.
+            let total = wm + (wa + wb) * 0.25;
+            if total == 0.0 {
+                continue;
+            }
+            let offset = middle.pos - (a.pos + b.pos) * 0.5;
+            let step = offset * (stiffness / total);
+            particles[c.middle].pos -= step * wm;
+            particles[c.a].pos += step * (wa * 0.5);
+            particles[c.b].pos += step * (wb * 0.5);
+        }
+    }
+
+    // Verlet keeps velocity as the step from `old_pos` to `pos`, so moving `pos` is what slows a
+    // particle down. The relative velocity along each spring is cut by `damping`, split by
+    // inverse mass like the inextensible solve, so pinned endpoints don't move.
+    fn damp_springs(&mut self, damping: f32) {
+        for spring in &self.springs {
+            let Some((p1, p2)) = pair_mut(&mut self.particles, spring.p1_idx, spring.p2_idx) else { continue };
+            let Some(axis) = (p2.pos - p1.pos).try_normalize() else { continue };
+            let w1 = if p1.is_pinned { 0.0 } else { 1.0 / p1.mass };
+            let w2 = if p2.is_pinned { 0.0 } else { 1.0 / p2.mass };
+            let total = w1 + w2;
+            if total == 0.0 {
+                continue;
+            }
+            // Positive while the spring is lengthening.
+            let rate = ((p2.pos - p2.old_pos) - (p1.pos - p1.old_pos)).dot(axis);
+            let change = axis * rate * damping;
+            p1.pos += change * (w1 / total);
+            p2.pos -= change * (w2 / total);
+        }
+    }
+
+    // Every correction is computed from the positions at the start of the pass and then
+    // averaged per particle, so each particle moves by the mean of what its springs ask for.
+    fn solve_springs_jacobi(&mut self, params: &SimParams, kinds: SpringLayers) {
+        self.jacobi_corrections.clear();
+        self.jacobi_corrections.resize(self.particles.len(), (Vec2::ZERO, 0));
+
+        for spring in self.springs.iter().filter(|s| kinds.includes(s.kind)) {
+            let (p1, p2) = (&self.particles[spring.p1_idx], &self.particles[spring.p2_idx]);
+            let Some((c1, c2)) = spring_correction(p1, p2, spring, params) else { continue };
+            for (idx, correction) in [(spring.p1_idx, c1), (spring.p2_idx, c2)] {
+                let (sum, count) = &mut self.jacobi_corrections[idx];
+                *sum += correction;
+                *count += 1;
+            }
+        }
+
+        for (p, &(sum, count)) in self.particles.iter_mut().zip(&self.jacobi_corrections) {
+            if count > 0 {
+                p.pos += sum / count as f32;
+            }
+        }
+    }
+
+    // Springs of one color share no particles, so their corrections can be computed in parallel
+    // from the same positions and applied afterwards. Colors are still solved one after another,
+    // which keeps the result close to the serial Gauss-Seidel pass.
+    fn solve_springs_parallel(&mut self, params: &SimParams, kinds: SpringLayers) {
+        let colors = self.spring_colors.get_or_insert_with(|| color_springs(&self.springs, self.particles.len()));
+
+        for group in colors.iter() {
+            let particles = &self.particles;
+            let springs = &self.springs;
+            let corrections: Vec<(Vec2, Vec2)> = group.par_iter().map(|&i| {
+                let spring = &springs[i];
+                if !kinds.includes(spring.kind) {
+                    return (Vec2::ZERO, Vec2::ZERO);
+                }
+                spring_correction(&particles[spring.p1_idx], &particles[spring.p2_idx], spring, params)
+                    .unwrap_or((Vec2::ZERO, Vec2::ZERO))
+            }).collect();
+
+            for (&i, (c1, c2)) in group.iter().zip(corrections) {
+                let spring = &self.springs[i];
+                self.particles[spring.p1_idx].pos += c1;
+                self.particles[spring.p2_idx].pos += c2;
+            }
+        }
+    }
+
+    // Pushes apart particles closer than `min_dist` unless a spring already joins them.
+    fn resolve_self_collision(&mut self, min_dist: f32) {
+        self.collision_hash.rebuild(self.particles.iter().map(|p| p.pos));
+        let springs = &self.springs;
+        let spring_pairs = self.spring_pairs.get_or_insert_with(|| {
+            springs.iter().map(|s| (s.p1_idx.min(s.p2_idx), s.p1_idx.max(s.p2_idx))).collect()
+        });
+
+        for i in 0..self.particles.len() {
+            for j in self.collision_hash.query_radius(self.particles[i].pos, min_dist) {
+                if j <= i || spring_pairs.contains(&(i, j)) {
+                    continue;
+                }
+
+                let (p1, p2) = (self.particles[i], self.particles[j]);
+                if (p1.is_pinned && p2.is_pinned) || p1.absent || p2.absent {
+                    continue;
+                }
+                let delta = p2.pos - p1.pos;
+                let dist = delta.length();
+                if dist >= min_dist || dist == 0.0 {
+                    continue;
+                }
+
+                let correction = delta * ((min_dist - dist) / dist);
+                if p1.is_pinned {
+                    self.particles[j].pos += correction;
+                } else if p2.is_pinned {
+                    self.particles[i].pos -= correction;
+                } else {
+                    self.particles[i].pos -= correction * 0.5;
+                    self.particles[j].pos += correction * 0.5;
+                }
+            }
+        }
+    }
+
+    // Pushes particles within `radius` of `center` straight outwards, with a force falling off
+    // linearly to zero at the edge. It goes through `apply_force`, so it only acts for the next
+    // step and heavy particles move less. Springs stretched past their threshold tear as usual.
+    pub fn apply_radial_impulse(&mut self, center: Vec2, radius: f32, strength: f32) {
+        for p in self.particles.iter_mut() {
+            let offset = p.pos - center;
+            let dist = offset.length();
+            if dist >= radius || dist == 0.0 {
+                continue;
+            }
+            p.apply_force(offset / dist * strength * (1.0 - dist / radius));
+        }
+    }
+
+    // Returns how many springs were removed.
+    pub fn cut(&mut self, center: Vec2, radius: f32) -> usize {
+        let spring_count = self.springs.len();
+        let particles = &self.particles;
+        let removed = &mut self.removed_springs;
+        self.springs.retain(|spring| {
+            let p1 = particles[spring.p1_idx];
+            let p2 = particles[spring.p2_idx];
+            let dist_to_spring = distance_point_to_segment(center, p1.pos, p2.pos);
+            if dist_to_spring <= radius {
+                removed.push(*spring);
+            }
+            dist_to_spring > radius
+        });
+        if self.springs.len() != spring_count {
+            self.springs_changed();
+        }
+        spring_count - self.springs.len()
+    }
+
+    // Separates the fabric along the stroke from `from` to `to` instead of cutting a hole.
+    // Particles within `radius` of the stroke count as lying on the cut line. Each of their
+    // springs to a particle off the line is sorted by which side of the stroke that particle is
+    // on, and a particle with springs on both sides is duplicated so the copy takes the ones on
+    // the left. Springs between two particles on the line run along the cut and stay with the
+    // originals, on the right. Springs spanning the stroke between two particles off the line
+    // are simply removed. Copies are appended after the grid, so they're left out of the filled
+    // mesh, and undo merges them back into their originals. Returns how many springs were
+    // severed, counting both the removed ones and the ones moved onto copies.
+    pub fn hard_cut(&mut self, from: Vec2, to: Vec2, radius: f32) -> usize {
+        let Some(direction) = (to - from).try_normalize() else { return 0 };
+        let length = from.distance(to);
+        let side = |pos: Vec2| direction.perp_dot(pos - from);
+        let on_cut: Vec<bool> = self.particles.iter()
+            .map(|p| distance_point_to_segment(p.pos, from, to) <= radius)
+            .collect();
+
+        let spring_count = self.springs.len();
+        let particles = &self.particles;
+        let removed = &mut self.removed_springs;
+        self.springs.retain(|s| {
+            if on_cut[s.p1_idx] || on_cut[s.p2_idx] {
+                return true;
+            }
+            let (a, b) = (particles[s.p1_idx].pos, particles[s.p2_idx].pos);
+            let (side_a, side_b) = (side(a), side(b));
+            if side_a * side_b >= 0.0 {
+                return true;
+            }
+            let crossing = a + (b - a) * (side_a / (side_a - side_b));
+            let along = (crossing - from).dot(direction);
+            let intact = along < 0.0 || along > length;
+            if !intact {
+                removed.push(*s);
+            }
+            intact
+        });
+        let mut severed = spring_count - self.springs.len();
+
+        // A particle only needs a copy if some of its springs stay behind on the right.
+        let mut left_springs = vec![Vec::new(); self.particles.len()];
+        let mut keeps_springs = vec![false; self.particles.len()];
+        for (i, spring) in self.springs.iter().enumerate() {
+            for (end, other) in [(spring.p1_idx, spring.p2_idx), (spring.p2_idx, spring.p1_idx)] {
+                if !on_cut[end] {
+                    continue;
+                }
+                if !on_cut[other] && side(self.particles[other].pos) > 0.0 {
+                    left_springs[end].push(i);
+                } else {
+                    keeps_springs[end] = true;
+                }
+            }
+        }
+
+        for (idx, springs) in left_springs.into_iter().enumerate() {
+            if springs.is_empty() || !keeps_springs[idx] {
+                continue;
+            }
+            let copy_idx = self.particles.len();
+            self.particles.push(self.particles[idx]);
+            self.split_particles.push((copy_idx, idx));
+            severed += springs.len();
+            for i in springs {
+                let spring = &mut self.springs[i];
+                if spring.p1_idx == idx {
+                    spring.p1_idx = copy_idx;
+                } else {
+                    spring.p2_idx = copy_idx;
+                }
+            }
+        }
+        if severed > 0 {
+            self.springs_changed();
+        }
+        severed
+    }
+
+    // Groups everything cut, torn or split during this frame into a single undo entry.
+    pub fn end_frame(&mut self) {
+        if self.removed_springs.is_empty() && self.split_particles.is_empty() {
+            return;
+        }
+        self.undo_stack.push_back(Removal {
+            springs: std::mem::take(&mut self.removed_springs),
+            splits: std::mem::take(&mut self.split_particles)
+        });
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    // Brings back the removed springs and merges each particle a hard cut split off back into
+    // its original, newest first, so the springs moved onto the copy rejoin the original.
+    // Returns how many springs were restored, counting the rejoined ones.
+    pub fn undo_removal(&mut self) -> usize {
+        let Some(removal) = self.undo_stack.pop_back() else { return 0 };
+        let mut count = removal.springs.len();
+        self.springs.extend(removal.springs);
+        for (copy_idx, original) in removal.splits.into_iter().rev() {
+            let mut repoint = |s: &mut Spring| {
+                let moved = s.p1_idx == copy_idx || s.p2_idx == copy_idx;
+                if s.p1_idx == copy_idx {
+                    s.p1_idx = original;
+                }
+                if s.p2_idx == copy_idx {
+                    s.p2_idx = original;
+                }
+                moved
+            };
+            count += self.springs.iter_mut().map(&mut repoint).filter(|&moved| moved).count();
+            self.removed_springs.iter_mut().for_each(|s| { repoint(s); });
+            for entry in self.undo_stack.iter_mut() {
+                entry.springs.iter_mut().for_each(|s| { repoint(s); });
+            }
+            // Nothing refers to the copy any more, so this only shifts later indices down.
+            self.remove_particle(copy_idx);
+        }
+        self.springs_changed();
+        count
+    }
+
+    // Appends a particle after the grid and returns its index. Like the copies a hard cut makes,
+    // it isn't part of the layout the cloth resets to.
+    pub fn add_particle(&mut self, particle: Particle) -> usize {
+        self.particles.push(particle);
+        self.particles.len() - 1
+    }
+
+    // Joins two particles with a spring that rests at their current distance. Returns false,
+    // adding nothing, if they're the same particle, out of range or already joined.
+    pub fn add_spring(&mut self, p1_idx: usize, p2_idx: usize, kind: SpringKind) -> bool {
+        let count = self.particles.len();
+        let joined = self.springs.iter()
+            .any(|s| (s.p1_idx, s.p2_idx) == (p1_idx, p2_idx) || (s.p1_idx, s.p2_idx) == (p2_idx, p1_idx));
+        if p1_idx == p2_idx || p1_idx >= count || p2_idx >= count || joined {
+            return false;
+        }
+        let rest_length = self.particles[p1_idx].pos.distance(self.particles[p2_idx].pos);
+        self.springs.push(Spring::new(p1_idx, p2_idx, rest_length, kind));
+        self.springs_changed();
+        true
+    }
+
+    // Removes one spring. It counts as cut, so undo brings it back.
+    pub fn remove_spring(&mut self, idx: usize) {
+        let spring = self.springs.swap_remove(idx);
+        self.removed_springs.push(spring);
+        self.springs_changed();
+    }
+
+    // Removes a particle along with every spring attached to it, including ones in the undo
+    // history and the reset layout. Indices stay compact rather than stable: every later particle
+    // moves down by one and all springs are remapped to match, so any index held from before
+    // the call is stale. Removing a grid particle leaves the rest without a grid layout, so the
+    // cloth has no quads to fill from then on.
+    pub fn remove_particle(&mut self, idx: usize) {
+        self.particles.remove(idx);
+        if idx < self.initial_positions.len() {
+            self.initial_positions.remove(idx);
+        }
+        let remap = |springs: &mut Vec<Spring>| {
+            springs.retain(|s| s.p1_idx != idx && s.p2_idx != idx);
+            for s in springs.iter_mut() {
+                s.p1_idx -= (s.p1_idx > idx) as usize;
+                s.p2_idx -= (s.p2_idx > idx) as usize;
+            }
+        };
+        // A split whose copy or original is gone can't be merged back any more.
+        let remap_splits = |splits: &mut Vec<(usize, usize)>| {
+            splits.retain(|&(copy_idx, original)| copy_idx != idx && original != idx);
+            for (copy_idx, original) in splits.iter_mut() {
+                *copy_idx -= (*copy_idx > idx) as usize;
+                *original -= (*original > idx) as usize;
+            }
+        };
+        remap(&mut self.springs);
+        remap(&mut self.initial_springs);
+        remap(&mut self.removed_springs);
+        remap_splits(&mut self.split_particles);
+        for entry in self.undo_stack.iter_mut() {
+            remap(&mut entry.springs);
+            remap_splits(&mut entry.splits);
+        }
+        self.undo_stack.retain(|entry| !entry.springs.is_empty() || !entry.splits.is_empty());
+        self.expected_degree = spring_degrees(&self.initial_springs, self.initial_positions.len());
+        if idx < self.width * self.height {
+            self.width = 0;
+            self.height = 0;
+        }
+        self.springs_changed();
+    }
+
+    // Puts every particle back where the cloth started, cold and at rest, and restores every
+    // spring, in place. Particles split off by hard cuts or added afterwards are dropped. Pins
+    // and masses are kept.
+    pub fn reset_positions(&mut self) {
+        self.particles.truncate(self.initial_positions.len());
+        for (p, &pos) in self.particles.iter_mut().zip(&self.initial_positions) {
+            p.pos = pos;
+            p.old_pos = pos;
+            p.acceleration = Vec2::ZERO;
+            p.heat = 0.0;
+        }
+        self.springs.clone_from(&self.initial_springs);
+        self.removed_springs.clear();
+        self.split_particles.clear();
+        self.undo_stack.clear();
+        self.springs_changed();
+    }
+
+    pub fn snapshot(&self) -> ClothState {
+        ClothState {
+            particles: self.particles.clone(),
+            springs: self.springs.clone(),
+            width: self.width,
+            height: self.height,
+            spacing: self.spacing,
+            initial_positions: self.initial_positions.clone(),
+            initial_springs: self.initial_springs.clone()
+        }
+    }
+
+    // The undo history refers to springs of the replaced state, so it is dropped.
+    pub fn restore(&mut self, state: &ClothState) {
+        self.particles = state.particles.clone();
+        self.springs = state.springs.clone();
+        self.width = state.width;
+        self.height = state.height;
+        self.spacing = state.spacing;
+        self.initial_positions = state.initial_positions.clone();
+        self.initial_springs = state.initial_springs.clone();
+        self.expected_degree = spring_degrees(&self.initial_springs, self.initial_positions.len());
+        self.removed_springs.clear();
+        self.split_particles.clear();
+        self.undo_stack.clear();
+        self.springs_changed();
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ClothFileError> {
+        let saved = SavedCloth {
+            width: self.width,
+            height: self.height,
+            spacing_x: self.spacing.x,
+            spacing_y: Some(self.spacing.y),
+            particles: self.particles.iter().map(|p| SavedParticle {
+                pos: p.pos.to_array(),
+                old_pos: p.old_pos.to_array(),
+                mass: p.mass,
+                is_pinned: p.is_pinned,
+                absent: p.absent
+            }).collect(),
+            springs: self.springs.iter().map(|s| SavedSpring {
+                p1_idx: s.p1_idx,
+                p2_idx: s.p2_idx,
+                rest_length: s.rest_length,
+                kind: s.kind,
+                strength: s.strength
+            }).collect()
+        };
+        std::fs::write(path, serde_json::to_string(&saved)?)?;
+        Ok(())
+    }
+
+    // Writes one vertex per particle and two triangles per intact quad. Vertices keep the
+    // screen coordinates (x right, y down) with z = 0, so the cloth appears upside down in
+    // y-up tools like Blender unless Y is flipped on import. OBJ face indices are 1-based.
+    pub fn export_obj(&self, path: &str) -> std::io::Result<()> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(out, "# 2D cloth export: {}x{} particles", self.width, self.height)?;
+        for p in &self.particles {
+            writeln!(out, "v {} {} 0", p.pos.x, p.pos.y)?;
+        }
+        for [a, b, c, d] in self.intact_quads() {
+            writeln!(out, "f {} {} {}", a + 1, b + 1, c + 1)?;
+            writeln!(out, "f {} {} {}", a + 1, c + 1, d + 1)?;
+        }
+        out.flush()
+    }
+
+    pub fn load(path: &str) -> Result<Self, ClothFileError> {
+        Cloth::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    fn from_json(json: &str) -> Result<Self, ClothFileError> {
+        let saved: SavedCloth = serde_json::from_str(json)?;
+
+        let particle_count = saved.particles.len();
+        let grid_count = saved.width.checked_mul(saved.height)
+            .ok_or(ClothFileError::SizeOverflow { width: saved.width, height: saved.height })?;
+        // Hard cuts append particles past the grid, so a save may hold more than width * height.
+        if particle_count < grid_count {
+            return Err(ClothFileError::ParticleCount { expected: grid_count, found: particle_count });
+        }
+        let spacing = vec2(saved.spacing_x, saved.spacing_y.unwrap_or(saved.spacing_x));
+        if !(spacing.is_finite() && spacing.min_element() > 0.0) {
+            return Err(ClothFileError::InvalidSpacing { x: spacing.x, y: spacing.y });
+        }
+        if let Some(spring) = saved.springs.iter().position(|s| s.p1_idx >= particle_count || s.p2_idx >= particle_count) {
+            return Err(ClothFileError::SpringOutOfBounds { spring, particle_count });
+        }
+
+        let particles = saved.particles.iter().map(|p| Particle {
+            pos: Vec2::from_array(p.pos),
+            old_pos: Vec2::from_array(p.old_pos),
+            acceleration: Vec2::ZERO,
+            mass: p.mass.max(MIN_MASS),
+            is_pinned: p.is_pinned || p.absent,
+            heat: 0.0,
+            absent: p.absent
+        }).collect();
+        let springs = saved.springs.iter().map(|s| Spring {
+            p1_idx: s.p1_idx,
+            p2_idx: s.p2_idx,
+            rest_length: s.rest_length,
+            kind: s.kind,
+            strength: s.strength.max(1.0)
+        }).collect();
+
+        Ok(Cloth::from_parts(particles, springs, saved.width, saved.height, spacing))
+    }
+
+    // Grid quads (corners in clockwise screen order) whose four edge springs are all intact, for
+    // anything that fills, measures or exports the fabric.
+    pub fn intact_quads(&self) -> impl Iterator<Item = [usize; 4]> + '_ {
+        let edges: HashSet<(usize, usize)> = self.springs.iter()
+            .map(|s| (s.p1_idx.min(s.p2_idx), s.p1_idx.max(s.p2_idx)))
+            .collect();
+        let width = self.width;
+
+        (0..self.height.saturating_sub(1))
+            .flat_map(move |y| (0..width.saturating_sub(1)).map(move |x| y * width + x))
+            .map(move |top_left| [top_left, top_left + 1, top_left + width + 1, top_left + width])
+            .filter(move |quad| (0..4).all(|i| {
+                let (a, b) = (quad[i], quad[(i + 1) % 4]);
+                edges.contains(&(a.min(b), a.max(b)))
+            }))
+    }
+
+    // Bytes taken by the particles and springs, which is where nearly all of a cloth's memory goes.
+    pub fn memory_footprint(&self) -> usize {
+        footprint(self.particles.len(), self.springs.len())
+    }
+
+    // Corners of the axis-aligned box around every present particle, or None for an empty cloth.
+    pub fn bounds(&self) -> Option<(Vec2, Vec2)> {
+        let mut present = self.particles.iter().filter(|p| !p.absent);
+        let first = present.next()?.pos;
+        Some(present.fold((first, first), |(min, max), p| (min.min(p.pos), max.max(p.pos))))
+    }
+
+    // Total area of the intact quads, each by the shoelace formula, so torn quads no longer count.
+    pub fn area(&self) -> f32 {
+        self.intact_quads().map(|quad| {
+            let twice_area: f32 = (0..4).map(|i| {
+                let (a, b) = (self.particles[quad[i]].pos, self.particles[quad[(i + 1) % 4]].pos);
+                a.perp_dot(b)
+            }).sum();
+            twice_area.abs() * 0.5
+        }).sum()
+    }
+
+    // Marks particles that have fewer springs than when the cloth was built. Particles split off
+    // by a hard cut always lie on a cut, so they count as torn too.
+    pub fn torn_particles(&self) -> Vec<bool> {
+        spring_degrees(&self.springs, self.particles.len()).iter().enumerate()
+            .map(|(i, &d)| self.expected_degree.get(i).is_none_or(|&expected| d < expected))
+            .collect()
+    }
+
+    // Labels each particle with the piece of cloth it belongs to, numbered from 0 in order of
+    // each piece's first particle. A particle with no springs left is a piece of its own.
+    pub fn component_labels(&self) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.particles.len()).collect();
+        for spring in &self.springs {
+            let (a, b) = (find_root(&mut parent, spring.p1_idx), find_root(&mut parent, spring.p2_idx));
+            parent[a.max(b)] = a.min(b);
+        }
+        let mut labels = vec![0; parent.len()];
+        let mut count = 0;
+        for i in 0..parent.len() {
+            let root = find_root(&mut parent, i);
+            labels[i] = if root == i {
+                count += 1;
+                count - 1
+            } else {
+                labels[root]
+            };
+        }
+        labels
+    }
+
+    // How many separate pieces the cloth has fallen into, see `component_labels`. Absent
+    // particles aren't pieces.
+    pub fn connected_components(&self) -> usize {
+        let labels = self.component_labels();
+        let pieces: HashSet<usize> = labels.into_iter().zip(&self.particles)
+            .filter(|(_, p)| !p.absent)
+            .map(|(label, _)| label)
+            .collect();
+        pieces.len()
+    }
+}
+
+// Union-find lookup with path halving.
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+// Borrows two distinct particles mutably at once by splitting the slice between them.
+fn pair_mut(particles: &mut [Particle], a: usize, b: usize) -> Option<(&mut Particle, &mut Particle)> {
+    if a == b {
+        return None;
+    }
+    if a < b {
+        let (low, high) = particles.split_at_mut(b);
+        Some((&mut low[a], &mut high[0]))
+    } else {
+        let (low, high) = particles.split_at_mut(a);
+        Some((&mut high[0], &mut low[b]))
+    }
+}
+
+// How far each endpoint of `spring` moves to bring it back towards its rest length. Pinned
+// endpoints never move.
+fn spring_correction(p1: &Particle, p2: &Particle, spring: &Spring, params: &SimParams) -> Option<(Vec2, Vec2)> {
+    let delta = p2.pos - p1.pos;
+    let dist = delta.length();
+    if dist == 0.0 {
+        return None;
+    }
+    let diff = (dist - params.rest_length(spring)) / dist;
+
+    match params.solve_mode {
+        SolveMode::Elastic => {
+            let mut stiffness = params.stiffness_for(spring.kind) * params.heat_factor((p1.heat + p2.heat) * 0.5);
+            if spring.strength != 1.0 {
+                // The share of the error `strength` passes at the plain stiffness would fix.
+                stiffness = 1.0 - (1.0 - stiffness).max(0.0).powf(spring.strength);
+            }
+            let correction = delta * params.relaxation * diff * stiffness;
+            let c1 = if p1.is_pinned { Vec2::ZERO } else { correction };
+            let c2 = if p2.is_pinned { Vec2::ZERO } else { -correction };
+            Some((c1, c2))
+        }
+        // Removes the whole error, split by inverse mass, so the spring ends up exactly at rest
+        // length. A pinned endpoint has no inverse mass and the free one takes all of it.
+        SolveMode::Inextensible => {
+            let w1 = if p1.is_pinned { 0.0 } else { 1.0 / p1.mass };
+            let w2 = if p2.is_pinned { 0.0 } else { 1.0 / p2.mass };
+            let total = w1 + w2;
+            if total == 0.0 {
+                return None;
+            }
+            let error = delta * diff;
+            Some((error * (w1 / total), -error * (w2 / total)))
+        }
+    }
+}
+
+// Triples of neighbours along every row and column of a `width` by `height` grid whose two
+// edges both still have a spring.
+fn grid_bend_constraints(springs: &[Spring], width: usize, height: usize) -> Vec<BendConstraint> {
+    let edges: HashSet<(usize, usize)> = springs.iter()
+        .map(|s| (s.p1_idx.min(s.p2_idx), s.p1_idx.max(s.p2_idx)))
+        .collect();
+    let mut constraints = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let middle = y * width + x;
+            if x > 0 && x + 1 < width {
+                constraints.push(BendConstraint { a: middle - 1, middle, b: middle + 1 });
+            }
+            if y > 0 && y + 1 < height {
+                constraints.push(BendConstraint { a: middle - width, middle, b: middle + width });
+            }
+        }
+    }
+    constraints.retain(|c| edges.contains(&(c.a, c.middle)) && edges.contains(&(c.middle, c.b)));
+    constraints
+}
+
+// How many springs end at each particle.
+fn spring_degrees(springs: &[Spring], particle_count: usize) -> Vec<u32> {
+    let mut degrees = vec![0; particle_count];
+    for spring in springs {
+        degrees[spring.p1_idx] += 1;
+        degrees[spring.p2_idx] += 1;
+    }
+    degrees
+}
+
+// Greedy edge coloring: each spring takes the lowest color neither of its particles uses yet.
+fn color_springs(springs: &[Spring], particle_count: usize) -> Vec<Vec<usize>> {
+    let mut colors: Vec<Vec<usize>> = Vec::new();
+    let mut particle_colors: Vec<Vec<bool>> = vec![Vec::new(); particle_count];
+
+    for (i, spring) in springs.iter().enumerate() {
+        let taken = |idx: usize, color: usize| particle_colors[idx].get(color).copied().unwrap_or(false);
+        let color = (0..).find(|&c| !taken(spring.p1_idx, c) && !taken(spring.p2_idx, c)).unwrap();
+
+        if color == colors.len() {
+            colors.push(Vec::new());
+        }
+        colors[color].push(i);
+        for idx in [spring.p1_idx, spring.p2_idx] {
+            if particle_colors[idx].len() <= color {
+                particle_colors[idx].resize(color + 1, false);
+            }
+            particle_colors[idx][color] = true;
+        }
+    }
+    colors
+}
+
+pub fn distance_point_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let ap = p - a;
+    let len_sq = ab.length_squared();
+
+    if len_sq == 0.0 {
+        return p.distance(a);
+    }
+
+    let t = (ap.dot(ab) / len_sq).clamp(0.0, 1.0);
+
+    let closest_point = a + t * ab;
+    p.distance(closest_point)
+}
+
+pub struct EnergyStats {
+    pub kinetic: f32,
+    pub potential: f32,
+    pub center_of_mass: Vec2
+}
+
+// Velocities come from each cloth's last substep, and absent particles are left out. Potential
+// energy is measured from `reference` along `gravity`, so it is zero at that height and grows
+// against gravity.
+pub fn energy_stats<'a>(cloths: impl IntoIterator<Item = &'a Cloth>, gravity: Vec2, reference: Vec2) -> EnergyStats {
+    let mut stats = EnergyStats { kinetic: 0.0, potential: 0.0, center_of_mass: Vec2::ZERO };
+    let mut total_mass = 0.0;
+    for cloth in cloths {
+        for (idx, p) in cloth.particles.iter().enumerate().filter(|(_, p)| !p.absent) {
+            stats.kinetic += 0.5 * p.mass * cloth.velocity(idx).length_squared();
+            stats.potential -= p.mass * gravity.dot(p.pos - reference);
+            stats.center_of_mass += p.pos * p.mass;
+            total_mass += p.mass;
+        }
+    }
+    if total_mass > 0.0 {
+        stats.center_of_mass /= total_mass;
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_params(parallel: bool) -> SimParams<'static> {
+        SimParams { iterations: 20, parallel, ..SimParams::default() }
+    }
+
+    #[test]
+    fn single_constraint_iteration_matches_reference() {
+        let mut cloth = Cloth::new(&ClothConfig::new(3, 3, 10.0, Vec2::ZERO));
+        cloth.particles[4].pos = vec2(13.0, 8.0);
+        cloth.particles[8].pos = vec2(21.0, 24.0);
+
+        cloth.solve_springs(&test_params(false), SpringLayers::ALL);
+
+        // Captured from the solver before it was rewritten without raw pointers.
+        let expected = [
+            vec2(0.0, 0.0),
+            vec2(10.0, 0.0),
+            vec2(20.0, 0.0),
+            vec2(0.3840036, 9.879272),
+            vec2(11.11606, 10.637589),
+            vec2(20.44066, 10.758648),
+            vec2(0.35025337, 19.661476),
+            vec2(10.3165655, 19.919352),
+            vec2(20.279331, 20.895561),
+        ];
+        let positions: Vec<Vec2> = cloth.particles.iter().map(|p| p.pos).collect();
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn tearing_a_spring_drops_only_its_quads() {
+        let mut cloth = Cloth::new(&ClothConfig::new(3, 3, 10.0, Vec2::ZERO));
+        assert_eq!(cloth.intact_quads().count(), 4);
+
+        // The structural spring down the middle of the top row of quads borders both of them.
+        cloth.springs.retain(|s| (s.p1_idx.min(s.p2_idx), s.p1_idx.max(s.p2_idx)) != (1, 4));
+
+        let quads: Vec<[usize; 4]> = cloth.intact_quads().collect();
+        assert_eq!(quads, [[3, 4, 7, 6], [4, 5, 8, 7]]);
+    }
+
+    fn spring_counts(cloth: &Cloth) -> [usize; 3] {
+        [SpringKind::Structural, SpringKind::Shear, SpringKind::Bend]
+            .map(|kind| cloth.springs.iter().filter(|s| s.kind == kind).count())
+    }
+
+    #[test]
+    fn single_particle_cloth_is_widened_to_two_by_two() {
+        let cloth = Cloth::new(&ClothConfig::new(1, 1, 10.0, Vec2::ZERO));
+        assert_eq!((cloth.width(), cloth.height()), (2, 2));
+        assert_eq!(cloth.particles.len(), 4);
+        assert_eq!(spring_counts(&cloth), [4, 2, 0]);
+        assert_eq!(cloth.intact_quads().count(), 1);
+    }
+
+    #[test]
+    fn two_by_two_cloth_has_no_bend_springs() {
+        let mut cloth = Cloth::new(&ClothConfig::new(2, 2, 10.0, Vec2::ZERO));
+        assert_eq!(cloth.particles.len(), 4);
+        assert_eq!(spring_counts(&cloth), [4, 2, 0]);
+        cloth.update(FIXED_DT, &test_params(false));
+    }
+
+    #[test]
+    fn one_wide_cloth_becomes_a_strip() {
+        let mut cloth = Cloth::new(&ClothConfig::new(1, 10, 10.0, Vec2::ZERO));
+        assert_eq!((cloth.width(), cloth.height()), (2, 10));
+        // 10 rungs and 2 sides of 9, 2 diagonals in each of 9 quads, and 8 bends down each side.
+        assert_eq!(spring_counts(&cloth), [28, 18, 16]);
+        assert_eq!(cloth.intact_quads().count(), 9);
+        cloth.update(FIXED_DT, &test_params(false));
+    }
+
+    #[test]
+    fn circular_mask_leaves_the_corners_absent() {
+        // Only the four corner cells of a 4x4 grid are further than 2 from its center.
+        let mask: Vec<bool> = (0..16)
+            .map(|i| vec2((i % 4) as f32 - 1.5, (i / 4) as f32 - 1.5).length() < 2.0)
+            .collect();
+        let cloth = Cloth::from_mask(&mask, 4, 4, vec2(10.0, 10.0), Vec2::ZERO);
+        assert_eq!((cloth.width(), cloth.height()), (4, 4));
+        assert_eq!(cloth.particles.len(), 16);
+        let absent: Vec<usize> = (0..16).filter(|&i| cloth.particles[i].absent).collect();
+        assert_eq!(absent, [0, 3, 12, 15]);
+        assert!(cloth.springs.iter().all(|s| !absent.contains(&s.p1_idx) && !absent.contains(&s.p2_idx)));
+        // Each corner quad keeps the one diagonal that misses its absent corner.
+        assert_eq!(spring_counts(&cloth), [16, 14, 8]);
+        assert_eq!(cloth.intact_quads().count(), 5);
+        assert_eq!(cloth.connected_components(), 1);
+    }
+
+    #[test]
+    fn held_particle_moves_at_most_its_limit_per_substep() {
+        let mut cloth = Cloth::new(&ClothConfig::new(3, 3, 10.0, Vec2::ZERO));
+        let start = cloth.particles[4].pos;
+        let brush = [(5, 0.5)];
+        let held = HeldParticle { idx: 4, target: start + vec2(0.0, 100.0), max_step: 2.0, brush: &brush };
+        let params = SimParams { iterations: 0, substeps: 4, gravity: Vec2::ZERO, held: Some(held), ..test_params(false) };
+
+        cloth.update(FIXED_DT, &params);
+
+        assert!(cloth.particles[4].pos.distance(start + vec2(0.0, 8.0)) < 1e-4);
+        assert!(cloth.particles[5].pos.distance(vec2(20.0, 14.0)) < 1e-4);
+        // The drag is a move, not a push, so nothing carries on once it's let go.
+        assert_eq!(cloth.velocity(4), Vec2::ZERO);
+        assert_eq!(cloth.velocity(5), Vec2::ZERO);
+    }
+
+    #[test]
+    fn energy_stats_leave_out_absent_particles() {
+        let config = ClothConfig { shape: ClothShape::Disc, ..ClothConfig::new(8, 8, 10.0, Vec2::ZERO) };
+        let mut cloth = Cloth::new(&config);
+        // A disc is symmetric, so shift what's present off the grid's center to tell them apart.
+        for p in cloth.particles.iter_mut().filter(|p| !p.absent) {
+            p.pos += vec2(30.0, 0.0);
+        }
+        let gravity = SimParams::default().gravity;
+        let present: Vec<&Particle> = cloth.particles.iter().filter(|p| !p.absent).collect();
+        assert!(present.len() < cloth.particles.len());
+        let mass: f32 = present.iter().map(|p| p.mass).sum();
+        let center = present.iter().map(|p| p.pos * p.mass).sum::<Vec2>() / mass;
+        let potential: f32 = present.iter().map(|p| -p.mass * gravity.dot(p.pos)).sum();
+
+        let stats = energy_stats([&cloth], gravity, Vec2::ZERO);
+        assert!(stats.center_of_mass.distance(center) < 1e-3, "{} vs {}", stats.center_of_mass, center);
+        assert!((stats.potential - potential).abs() <= potential.abs() * 1e-5);
+    }
+
+    #[test]
+    fn hard_cut_down_a_column_splits_the_cloth_in_two() {
+        let mut cloth = Cloth::new(&ClothConfig::new(6, 6, 10.0, Vec2::ZERO));
+        assert_eq!(cloth.connected_components(), 1);
+
+        // The stroke runs down the third column, so its particles lie on the cut and are split.
+        let severed = cloth.hard_cut(vec2(20.0, -5.0), vec2(20.0, 55.0), 1.0);
+        assert!(severed > 0);
+        assert_eq!(cloth.particles.len(), 36 + 6);
+        assert_eq!(cloth.connected_components(), 2);
+    }
+
+    #[test]
+    fn undoing_a_hard_cut_merges_the_copies_back() {
+        let mut cloth = Cloth::new(&ClothConfig::new(6, 6, 10.0, Vec2::ZERO));
+        let springs_before = spring_counts(&cloth);
+        cloth.hard_cut(vec2(20.0, -5.0), vec2(20.0, 55.0), 1.0);
+        cloth.end_frame();
+        assert_eq!(cloth.connected_components(), 2);
+
+        assert!(cloth.undo_removal() > 0);
+        assert_eq!(cloth.particles.len(), 36);
+        assert_eq!(spring_counts(&cloth), springs_before);
+        assert_eq!(cloth.connected_components(), 1);
+        assert!(cloth.torn_particles().iter().all(|&torn| !torn));
+        assert_eq!(cloth.undo_removal(), 0);
+    }
+
+    // A save of `particle_count` particles at the origin with no springs.
+    fn saved_cloth_json(width: usize, height: usize, spacing: f32, mass: f32, particle_count: usize) -> String {
+        let particles: Vec<SavedParticle> = (0..particle_count)
+            .map(|_| SavedParticle { pos: [0.0, 0.0], old_pos: [0.0, 0.0], mass, is_pinned: false, absent: false })
+            .collect();
+        let saved = SavedCloth { width, height, spacing_x: spacing, spacing_y: None, particles, springs: Vec::new() };
+        serde_json::to_string(&saved).unwrap()
+    }
+
+    #[test]
+    fn load_clamps_saved_masses() {
+        let cloth = Cloth::from_json(&saved_cloth_json(2, 2, 10.0, 0.0, 4)).unwrap();
+        assert!(cloth.particles.iter().all(|p| p.mass == MIN_MASS));
+    }
+
+    #[test]
+    fn load_rejects_an_overflowing_size() {
+        let err = Cloth::from_json(&saved_cloth_json(usize::MAX, 2, 10.0, 1.0, 4)).err().unwrap();
+        assert!(matches!(err, ClothFileError::SizeOverflow { .. }), "{}", err);
+    }
+
+    #[test]
+    fn load_rejects_too_few_particles() {
+        let err = Cloth::from_json(&saved_cloth_json(3, 3, 10.0, 1.0, 8)).err().unwrap();
+        assert!(matches!(err, ClothFileError::ParticleCount { expected: 9, found: 8 }), "{}", err);
+    }
+
+    #[test]
+    fn load_rejects_non_positive_spacing() {
+        for spacing in [0.0, -5.0] {
+            let err = Cloth::from_json(&saved_cloth_json(2, 2, spacing, 1.0, 4)).err().unwrap();
+            assert!(matches!(err, ClothFileError::InvalidSpacing { .. }), "{}", err);
+        }
+        // JSON has no infinities, but a spacing that overflows f32 must not get through either.
+        let json = saved_cloth_json(2, 2, 10.0, 1.0, 4).replace("\"spacing_x\":10.0", "\"spacing_x\":1e39");
+        assert!(Cloth::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn query_radius_matches_a_brute_force_search() {
+        // A jittered lattice with the same spacing as the cells puts points on both sides of
+        // every cell border, and a few sit exactly on one.
+        let mut positions: Vec<Vec2> = (0..15 * 15)
+            .map(|i| vec2((i % 15) as f32 * 10.0, (i / 15) as f32 * 10.0))
+            .collect();
+        let mut rng = XorShift64::new(7);
+        for pos in positions.iter_mut().skip(4) {
+            *pos += vec2(rng.next_signed(), rng.next_signed()) * 4.0;
+        }
+
+        let mut hash = SpatialHash::new(10.0);
+        // Rebuild from somewhere else first so stale buckets would show up in the results.
+        hash.rebuild(positions.iter().map(|&p| p + vec2(500.0, -300.0)));
+        hash.rebuild(positions.iter().copied());
+        assert_eq!(hash.cells.values().map(Vec::len).sum::<usize>(), positions.len());
+        assert!(hash.cells.values().all(|bucket| !bucket.is_empty()));
+
+        let queries = [(vec2(70.0, 70.0), 12.0), (vec2(30.0, 0.0), 10.0), (vec2(99.9, 45.0), 23.5), (vec2(-5.0, -5.0), 8.0)];
+        for (center, radius) in queries {
+            let mut found = hash.query_radius(center, radius);
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..positions.len())
+                .filter(|&i| positions[i].distance_squared(center) <= radius * radius)
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(found, expected, "query at {center} with radius {radius}");
+        }
+    }
+
+    #[test]
+    fn try_new_refuses_oversized_configs() {
+        let cap = 64 * 1024 * 1024;
+        let cloth = Cloth::try_new(&ClothConfig::new(40, 30, 10.0, Vec2::ZERO), cap).unwrap();
+        assert!(cloth.memory_footprint() <= cap);
+
+        let err = Cloth::try_new(&ClothConfig::new(20_000, 20_000, 10.0, Vec2::ZERO), cap).err().unwrap();
+        assert!(err.footprint > cap && err.limit == cap);
+
+        // Counting the particles of this one overflows, so it's refused even without a cap.
+        let huge = ClothConfig::new(usize::MAX / 2, 4, 10.0, Vec2::ZERO);
+        assert_eq!(huge.estimated_footprint(), usize::MAX);
+        assert!(Cloth::try_new(&huge, usize::MAX).is_err());
+    }
+}
+
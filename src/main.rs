@@ -1,292 +1,2199 @@
 use macroquad::prelude::*;
-use macroquad::ui::{hash, root_ui, widgets};
+use macroquad::ui::{hash, root_ui, widgets, Id, Ui};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 
-#[derive(Clone, Copy)]
-struct Particle {
-    pos: Vec2,
-    old_pos: Vec2,
-    acceleration: Vec2,
-    mass: f32,
-    is_pinned: bool
+use cloth::*;
+
+mod replay;
+mod shading;
+use replay::{Input, InputFrame, InputRecording, Replay};
+
+const SAVE_PATH: &str = "cloth_save.json";
+const TEXTURE_PATH: &str = "cloth_texture.png";
+const OBJ_PATH: &str = "cloth_export.obj";
+const SETTINGS_PATH: &str = "settings.json";
+const REPLAY_PATH: &str = "input_replay.txt";
+const RECORD_DIR: &str = "recording";
+// Simulated time per recorded frame, so recordings play back at a steady 60 fps.
+const RECORD_FRAME_DT: f32 = 1.0 / 60.0;
+const MAX_STEPS_PER_FRAME: usize = 8;
+const GRAVITY_TURN_RATE: f32 = 90.0;
+// How long Flip Gravity takes to swing gravity round to the opposite direction.
+const GRAVITY_FLIP_SECONDS: f64 = 0.75;
+// Light every lit triangle gets, whichever way it faces, on the CPU and in the shader alike.
+const AMBIENT_LIGHT: f32 = 0.25;
+const MASS_STEP: f32 = 0.5;
+// Cloth sizes the panel accepts, in particles per side.
+const MIN_CLOTH_SIZE: usize = 4;
+const MAX_CLOTH_SIZE: usize = 64;
+// The Settle button gives up after this many steps, and counts the cloth as settled once
+// nothing moves faster than the tolerance, in pixels per second.
+const SETTLE_MAX_STEPS: usize = 2400;
+const SETTLE_TOLERANCE: f32 = 1.0;
+// How long the spring count of the last cut or undo stays next to the cursor.
+const CUT_FEEDBACK_SECONDS: f64 = 1.5;
+// Every shortcut, for the help overlay and the hint line. The first two are the hint line.
+const BINDINGS: [(&str, &str); 29] = [
+    ("Left Mouse", "Drag and tear"),
+    ("Right Mouse", "Cut"),
+    ("Middle Mouse", "Pan the view"),
+    ("Mouse Wheel", "Zoom"),
+    ("Shift + Mouse Wheel", "Resize the cut brush"),
+    ("C", "Place an obstacle"),
+    ("B + Left Mouse", "Draw a box obstacle"),
+    ("P + Left Mouse", "Pin or unpin"),
+    ("A + Left Mouse", "Move an anchor"),
+    ("G + Left Mouse", "Start or stop a motion path"),
+    ("F + Left Mouse", "Flick"),
+    ("E + Left Mouse", "Burst"),
+    ("T + Left Mouse", "Heat"),
+    ("S + Left Mouse", "Reinforce"),
+    ("M + Left Mouse", "Add mass"),
+    ("M + Shift + Left Mouse", "Remove mass"),
+    ("Edit mode + Left Mouse", "Add a particle or spring"),
+    ("Edit mode + Right Mouse", "Delete a particle or spring"),
+    ("Space", "Pause"),
+    (".", "Step while paused"),
+    ("Left / Right", "Rotate gravity"),
+    ("R", "Reset the selected cloth"),
+    ("Ctrl + Z", "Undo the last cut or tear"),
+    ("F5", "Take a snapshot"),
+    ("F9", "Restore the snapshot"),
+    ("F3", "Performance overlay"),
+    ("F11", "Fullscreen"),
+    ("H", "Show or hide this help"),
+    ("Two fingers", "Cut with touch")
+];
+
+// UI values remembered between runs. Missing fields keep their defaults, so older files still load.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SavedSettings {
+    cloth_width: f32,
+    cloth_height: f32,
+    // Older files have a single `spacing`, which now sets the row spacing.
+    #[serde(alias = "spacing")]
+    spacing_x: f32,
+    spacing_y: f32,
+    stiffness: f32,
+    tear_threshold: f32,
+    gravity: f32,
+    gravity_angle: f32,
+    iterations: f32,
+    cut_radius: f32
 }
 
-impl Particle {
-    fn new(x: f32, y: f32) -> Self {
-        Particle {
-            pos: vec2(x, y),
-            old_pos: vec2(x, y),
-            acceleration: Vec2::ZERO,
-            mass: 1.0,
-            is_pinned: false
+impl Default for SavedSettings {
+    fn default() -> Self {
+        SavedSettings {
+            cloth_width: 40.0,
+            cloth_height: 25.0,
+            spacing_x: 15.0,
+            spacing_y: 15.0,
+            stiffness: 0.9,
+            tear_threshold: 4.5,
+            gravity: 980.0,
+            gravity_angle: 0.0,
+            iterations: 5.0,
+            cut_radius: 10.0
         }
     }
+}
 
-    fn update(&mut self, dt: f32) {
-        if self.is_pinned {
-            return;
+impl SavedSettings {
+    // The selected cloth's settings are the ones remembered for next time.
+    fn new(cloth: &ClothSettings, gravity: f32, gravity_angle: f32, cut_radius: f32) -> Self {
+        SavedSettings {
+            cloth_width: cloth.width,
+            cloth_height: cloth.height,
+            spacing_x: cloth.spacing_x,
+            spacing_y: cloth.spacing_y,
+            stiffness: cloth.stiffness,
+            tear_threshold: cloth.tear_threshold,
+            gravity,
+            gravity_angle,
+            iterations: cloth.iterations,
+            cut_radius
         }
+    }
+
+    // A fresh cloth at `start` with these settings and the defaults for everything else.
+    fn cloth_settings(&self, start: Vec2) -> ClothSettings {
+        let mut cloth = ClothSettings::new(self.cloth_width, self.cloth_height, self.spacing_x, start);
+        self.apply_to(&mut cloth);
+        cloth
+    }
+
+    fn apply_to(&self, cloth: &mut ClothSettings) {
+        cloth.width = self.cloth_width;
+        cloth.height = self.cloth_height;
+        cloth.spacing_x = self.spacing_x;
+        cloth.spacing_y = self.spacing_y;
+        cloth.stiffness = self.stiffness;
+        cloth.tear_threshold = self.tear_threshold;
+        cloth.iterations = self.iterations;
+    }
 
-        let velocity = self.pos - self.old_pos;
-        self.old_pos = self.pos;
-        self.pos += velocity + self.acceleration * dt * dt;
-        self.acceleration = Vec2::ZERO;
+    // Falls back to the defaults if the file is missing or can't be parsed. Values are kept
+    // within the ranges the UI allows, so a hand-edited file can't build a broken cloth.
+    fn load(path: &str) -> Self {
+        let settings: SavedSettings = std::fs::read_to_string(path).ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        SavedSettings {
+            cloth_width: settings.cloth_width.clamp(4.0, 64.0),
+            cloth_height: settings.cloth_height.clamp(4.0, 64.0),
+            spacing_x: settings.spacing_x.clamp(5.0, 40.0),
+            spacing_y: settings.spacing_y.clamp(5.0, 40.0),
+            iterations: settings.iterations.clamp(1.0, 20.0),
+            ..settings
+        }
     }
 
-    fn apply_force(&mut self, force: Vec2) {
-        self.acceleration += force / self.mass;
+    fn save(&self, path: &str) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
     }
 }
 
-struct Spring {
-    p1_idx: usize,
-    p2_idx: usize,
-    rest_length: f32
+#[derive(Clone, Copy, PartialEq)]
+enum DrawMode {
+    Wireframe,
+    Stress,
+    Speed,
+    Components,
+    Mesh,
+    Shaded,
+    Triangles
 }
 
-struct Cloth {
-    particles: Vec<Particle>,
-    springs: Vec<Spring>,
-    width: usize,
-    height: usize
+impl DrawMode {
+    const ALL: [DrawMode; 7] = [DrawMode::Wireframe, DrawMode::Stress, DrawMode::Speed, DrawMode::Components, DrawMode::Mesh, DrawMode::Shaded, DrawMode::Triangles];
+    const LABELS: [&'static str; 7] = ["Wireframe", "Stress", "Speed", "Components", "Mesh", "Shaded", "Triangles"];
 }
 
-impl Cloth {
-    fn new(width: usize, height: usize, spacing: f32, start_x: f32, start_y: f32) -> Self {
-        let mut particles = Vec::with_capacity(width * height);
-        for y in 0..height {
-            for x in 0..width {
-                let mut p = Particle::new(start_x + x as f32 * spacing, start_y + y as f32 * spacing);
-                if y == 0 {
-                    p.is_pinned = true;
-                }
-                particles.push(p);
+#[derive(Clone, Copy, PartialEq)]
+enum RenderPreset {
+    Default,
+    HighContrast,
+    Minimal
+}
+
+impl RenderPreset {
+    const ALL: [RenderPreset; 3] = [RenderPreset::Default, RenderPreset::HighContrast, RenderPreset::Minimal];
+    const LABELS: [&'static str; 3] = ["Default", "High contrast", "Minimal"];
+}
+
+// Sizes are in world units, so they scale with the zoom like the cloth itself.
+struct RenderConfig {
+    particle_radius: f32,
+    line_thickness: f32,
+    pinned_color: Color,
+    free_color: Color,
+    line_color: Color,
+    // Particles that lost springs and the free edges between them.
+    torn_color: Color,
+    // Springs strengthened with the reinforcing brush.
+    reinforced_color: Color,
+    // The filled mesh without a texture, or the tint over one.
+    fabric_color: Color,
+    // How bright the shaded mode's glow along edge-on folds is.
+    rim_strength: f32,
+    // Light the mesh views per particle rather than per triangle.
+    smooth_shading: bool
+}
+
+impl RenderConfig {
+    fn preset(preset: RenderPreset) -> Self {
+        match preset {
+            RenderPreset::Default => RenderConfig {
+                particle_radius: 2.0,
+                line_thickness: 1.0,
+                pinned_color: RED,
+                free_color: BLUE,
+                line_color: WHITE,
+                torn_color: ORANGE,
+                reinforced_color: SKYBLUE,
+                fabric_color: Color::new(0.55, 0.65, 0.9, 1.0),
+                rim_strength: 0.35,
+                smooth_shading: true
+            },
+            RenderPreset::HighContrast => RenderConfig {
+                particle_radius: 4.0,
+                line_thickness: 2.0,
+                pinned_color: Color::new(1.0, 0.2, 0.2, 1.0),
+                free_color: YELLOW,
+                line_color: WHITE,
+                torn_color: MAGENTA,
+                reinforced_color: GREEN,
+                fabric_color: Color::new(0.55, 0.65, 0.9, 1.0),
+                rim_strength: 0.35,
+                smooth_shading: true
+            },
+            RenderPreset::Minimal => RenderConfig {
+                particle_radius: 0.0,
+                line_thickness: 1.0,
+                pinned_color: RED,
+                free_color: GRAY,
+                line_color: Color::new(0.7, 0.7, 0.7, 1.0),
+                torn_color: ORANGE,
+                reinforced_color: SKYBLUE,
+                fabric_color: Color::new(0.55, 0.65, 0.9, 1.0),
+                rim_strength: 0.35,
+                smooth_shading: true
             }
         }
+    }
 
-        let mut springs = Vec::new();
-        for y in 0..height {
-            for x in 0..width {
-                let current_idx = y * width + x;
-                if x < width - 1 {
-                    springs.push(Spring { p1_idx: current_idx, p2_idx: current_idx + 1, rest_length: spacing });
-                    if x < width - 2 {
-                         springs.push(Spring { p1_idx: current_idx, p2_idx: current_idx + 2, rest_length: spacing * 2.0 });
-                    }
-                }
-                if y < height - 1 {
-                    springs.push(Spring { p1_idx: current_idx, p2_idx: current_idx + width, rest_length: spacing });
-                    if y < height - 2 {
-                        springs.push(Spring { p1_idx: current_idx, p2_idx: current_idx + (2 * width), rest_length: spacing * 2.0 });
-                    }
+    // Pinned particles are drawn half as large again so anchors stay easy to spot, and grow
+    // with the line thickness so thick lines don't bury them.
+    fn pinned_radius(&self) -> f32 {
+        (self.particle_radius * 1.5).max(self.line_thickness * 1.5).max(2.0)
+    }
+}
+
+// Rendering lives here rather than in the library so the simulation can run headless.
+trait ClothDrawing {
+    fn draw_filled(&self, texture: Option<&Texture2D>, render: &RenderConfig, light: Vec3);
+    fn draw_shaded(&self, material: &Material, texture: Option<&Texture2D>, render: &RenderConfig, light: Vec3);
+    fn draw(&self, tear_strain: Option<f32>, rest_length_scale: f32, render: &RenderConfig);
+    fn draw_speed(&self, max_speed: f32, render: &RenderConfig);
+    fn draw_components(&self, render: &RenderConfig);
+    fn draw_triangles(&self, render: &RenderConfig);
+}
+
+impl ClothDrawing for Cloth {
+    // Draws two triangles per intact quad. Torn quads leave holes in the fabric. Each triangle
+    // is lit on both sides by the directional `light`, see `triangle_normal`.
+    fn draw_filled(&self, texture: Option<&Texture2D>, render: &RenderConfig, light: Vec3) {
+        let base_color = if texture.is_some() { WHITE } else { render.fabric_color };
+        draw_cloth_mesh(self, texture, render.smooth_shading, |normal| {
+            let shade = normal.map_or(AMBIENT_LIGHT, |n| AMBIENT_LIGHT + (1.0 - AMBIENT_LIGHT) * n.dot(light).abs());
+            Color::new(base_color.r * shade, base_color.g * shade, base_color.b * shade, 1.0)
+        });
+        draw_mesh_markers(self, render);
+    }
+
+    // The same mesh as `draw_filled`, lit per pixel by the material from `shading`.
+    fn draw_shaded(&self, material: &Material, texture: Option<&Texture2D>, render: &RenderConfig, light: Vec3) {
+        let base_color = if texture.is_some() { WHITE } else { render.fabric_color };
+        shading::set_cloth_uniforms(material, light, base_color, render.rim_strength);
+        gl_use_material(material);
+        draw_cloth_mesh(self, texture, render.smooth_shading, |_| WHITE);
+        gl_use_default_material();
+        draw_mesh_markers(self, render);
+    }
+
+    // With `tear_strain` set, springs are colored by strain, reaching full red at that strain.
+    // Strain is measured against rest lengths scaled by `rest_length_scale`, as the solver does.
+    fn draw(&self, tear_strain: Option<f32>, rest_length_scale: f32, render: &RenderConfig) {
+        let torn = self.torn_particles();
+        for spring in self.springs() {
+            let p1 = self.particles()[spring.p1_idx];
+            let p2 = self.particles()[spring.p2_idx];
+            // A spring between two torn particles runs along a free edge.
+            let on_edge = torn[spring.p1_idx] && torn[spring.p2_idx];
+            let color = match tear_strain {
+                Some(max_strain) => {
+                    let rest_length = spring.rest_length * rest_length_scale;
+                    let strain = (p1.pos.distance(p2.pos) - rest_length) / rest_length;
+                    strain_color(strain, max_strain)
                 }
-                if x < width - 1 && y < height - 1 {
-                    let diagonal_len = (spacing.powi(2) + spacing.powi(2)).sqrt();
-                    springs.push(Spring { p1_idx: current_idx, p2_idx: current_idx + width + 1, rest_length: diagonal_len });
-                    springs.push(Spring { p1_idx: (y * width) + (x + 1), p2_idx: (y + 1) * width + x, rest_length: diagonal_len });
+                None if on_edge => render.torn_color,
+                None if spring.strength > 1.0 => render.reinforced_color,
+                None => render.line_color
+            };
+            let thickness = if on_edge { render.line_thickness * 2.0 } else { render.line_thickness };
+            draw_line(p1.pos.x, p1.pos.y, p2.pos.x, p2.pos.y, thickness, color);
+        }
+        for (p, &is_torn) in self.particles().iter().zip(&torn).filter(|(p, _)| !p.absent) {
+            // Scale the area of the marker with mass so heavy regions stand out.
+            let mass_scale = p.mass.sqrt();
+            if p.is_pinned {
+                draw_circle(p.pos.x, p.pos.y, render.pinned_radius() * mass_scale, render.pinned_color);
+            } else if p.heat > 0.0 {
+                draw_circle(p.pos.x, p.pos.y, render.particle_radius.max(2.0) * mass_scale, heat_color(p.heat));
+            } else if is_torn {
+                draw_circle(p.pos.x, p.pos.y, render.particle_radius.max(1.5) * mass_scale, render.torn_color);
+            } else if render.particle_radius > 0.0 {
+                draw_circle(p.pos.x, p.pos.y, render.particle_radius * mass_scale, render.free_color);
+            }
+        }
+    }
+
+    // Colors each particle by its speed over the last substep, in world units per second, on a
+    // fixed scale that saturates at `max_speed` so frames can be compared.
+    fn draw_speed(&self, max_speed: f32, render: &RenderConfig) {
+        let line_color = Color::new(render.line_color.r, render.line_color.g, render.line_color.b, 0.3);
+        for spring in self.springs() {
+            let (p1, p2) = (self.particles()[spring.p1_idx], self.particles()[spring.p2_idx]);
+            draw_line(p1.pos.x, p1.pos.y, p2.pos.x, p2.pos.y, render.line_thickness, line_color);
+        }
+        for (idx, p) in self.particles().iter().enumerate().filter(|(_, p)| !p.absent) {
+            let speed = self.velocity(idx).length();
+            draw_circle(p.pos.x, p.pos.y, render.particle_radius.max(2.0), speed_color(speed / max_speed));
+        }
+    }
+
+    // Gives each disconnected piece its own color, cycling through `COMPONENT_COLORS`.
+    fn draw_components(&self, render: &RenderConfig) {
+        let labels = self.component_labels();
+        let color = |idx: usize| COMPONENT_COLORS[labels[idx] % COMPONENT_COLORS.len()];
+        for spring in self.springs() {
+            let (p1, p2) = (self.particles()[spring.p1_idx], self.particles()[spring.p2_idx]);
+            draw_line(p1.pos.x, p1.pos.y, p2.pos.x, p2.pos.y, render.line_thickness, color(spring.p1_idx));
+        }
+        for (idx, p) in self.particles().iter().enumerate().filter(|(_, p)| !p.absent) {
+            let radius = if p.is_pinned { render.pinned_radius() } else { render.particle_radius.max(1.5) };
+            draw_circle(p.pos.x, p.pos.y, radius, color(idx));
+        }
+    }
+
+    // Debug view of the mesh: each triangle shrunk towards its centroid so gaps show between
+    // them, with the second triangle of every quad darker to show how it was split.
+    fn draw_triangles(&self, render: &RenderConfig) {
+        let fabric = render.fabric_color;
+        let colors = [fabric, Color::new(fabric.r * 0.6, fabric.g * 0.6, fabric.b * 0.6, 1.0)];
+        for quad in self.intact_quads() {
+            for (triangle, color) in QUAD_TRIANGLES.into_iter().zip(colors) {
+                let corners = triangle.map(|i| self.particles()[quad[i]].pos);
+                let centroid = (corners[0] + corners[1] + corners[2]) / 3.0;
+                let [a, b, c] = corners.map(|p| centroid + (p - centroid) * 0.8);
+                draw_triangle(a, b, c, color);
+            }
+        }
+        draw_mesh_markers(self, render);
+    }
+}
+
+// How each quad's [tl, tr, br, bl] corners are split into two triangles.
+const QUAD_TRIANGLES: [[usize; 3]; 2] = [[0, 1, 2], [0, 2, 3]];
+
+// Builds two triangles per intact quad and draws them in batches that fit u16 indices. Each
+// vertex's normal goes into its `normal` for shaders, and `color` picks its color from it. The
+// normal is the triangle's own, or with `smooth` the particle's from `smoothed_normals`.
+fn draw_cloth_mesh(cloth: &Cloth, texture: Option<&Texture2D>, smooth: bool, color: impl Fn(Option<Vec3>) -> Color) {
+    let s = cloth.spacing();
+    // Rest shapes of the two triangles, in the same [tl, tr, br, bl] corner order as the quads.
+    let rest_quad = [vec2(0.0, 0.0), vec2(s.x, 0.0), vec2(s.x, s.y), vec2(0.0, s.y)];
+    let quads: Vec<[usize; 4]> = cloth.intact_quads().collect();
+    let triangle_normals: Vec<[Option<Vec3>; 2]> = quads.iter()
+        .map(|quad| QUAD_TRIANGLES.map(|triangle| {
+            triangle_normal(triangle.map(|i| rest_quad[i]), triangle.map(|i| cloth.particles()[quad[i]].pos))
+        }))
+        .collect();
+    let particle_normals = smooth.then(|| smoothed_normals(cloth.particles().len(), &quads, &triangle_normals));
+    let mut mesh = Mesh { vertices: Vec::new(), indices: Vec::new(), texture: texture.cloned() };
+
+    for (quad, normals) in quads.iter().zip(&triangle_normals) {
+        if mesh.vertices.len() + 6 > u16::MAX as usize {
+            draw_mesh(&mesh);
+            mesh.vertices.clear();
+            mesh.indices.clear();
+        }
+
+        for (triangle, flat_normal) in QUAD_TRIANGLES.into_iter().zip(normals) {
+            let first = mesh.vertices.len() as u16;
+            for i in triangle {
+                let idx = quad[i];
+                let pos = cloth.particles()[idx].pos;
+                let normal = particle_normals.as_ref().map_or(*flat_normal, |normals| normals[idx]);
+                let u = (idx % cloth.width()) as f32 / (cloth.width() - 1) as f32;
+                let v = (idx / cloth.width()) as f32 / (cloth.height() - 1) as f32;
+                let mut vertex = Vertex::new(pos.x, pos.y, 0.0, u, v, color(normal));
+                vertex.normal = normal.unwrap_or(Vec3::Z).extend(0.0);
+                mesh.vertices.push(vertex);
+            }
+            mesh.indices.extend_from_slice(&[first, first + 1, first + 2]);
+        }
+    }
+
+    if !mesh.vertices.is_empty() {
+        draw_mesh(&mesh);
+    }
+}
+
+// Each particle's normal as the average of the triangles around it, so light changes smoothly
+// across quads instead of one facet at a time. Edge and torn particles just average fewer
+// triangles; one with none left, or whose triangles' tilts cancel out exactly, gets None.
+fn smoothed_normals(particle_count: usize, quads: &[[usize; 4]], triangle_normals: &[[Option<Vec3>; 2]]) -> Vec<Option<Vec3>> {
+    let mut sums = vec![Vec3::ZERO; particle_count];
+    for (quad, normals) in quads.iter().zip(triangle_normals) {
+        for (triangle, normal) in QUAD_TRIANGLES.iter().zip(normals) {
+            if let Some(normal) = normal {
+                for &i in triangle {
+                    sums[quad[i]] += *normal;
                 }
             }
         }
+    }
+    sums.into_iter().map(Vec3::try_normalize).collect()
+}
 
-        Cloth { particles, springs, width, height }
+// Hot and pinned particles on top of a filled mesh.
+fn draw_mesh_markers(cloth: &Cloth, render: &RenderConfig) {
+    for p in cloth.particles().iter().filter(|p| p.heat > 0.0) {
+        draw_circle(p.pos.x, p.pos.y, render.particle_radius.max(2.0), heat_color(p.heat));
+    }
+    for p in cloth.particles().iter().filter(|p| p.is_pinned && !p.absent) {
+        draw_circle(p.pos.x, p.pos.y, render.pinned_radius(), render.pinned_color);
     }
+}
 
-    fn update(
-        &mut self,
-        dt: f32,
-        iterations: usize,
-        gravity: Vec2,
-        stiffness: f32,
-        tear_threshold: f32,
-    ) {
-        for p in self.particles.iter_mut() {
-            p.apply_force(gravity);
+// The sim is flat, so the normal is estimated from how the triangle is deformed on screen,
+// treating the cloth as inextensible: it shrinks the most along the direction it tilts away in,
+// and the ratio of the least to the most stretched direction is the cosine of the tilt. The
+// sign of a tilt can't be recovered from a projection, so the cloth is treated as a height field
+// that rises towards the viewer along its rows. Lighting uses the absolute value of the normal
+// dot the light, so both sides of a fold are shaded alike. None for a collapsed triangle.
+fn triangle_normal(rest: [Vec2; 3], current: [Vec2; 3]) -> Option<Vec3> {
+    let rest_edges = Mat2::from_cols(rest[1] - rest[0], rest[2] - rest[0]);
+    let edges = Mat2::from_cols(current[1] - current[0], current[2] - current[0]);
+    let deformation = edges * rest_edges.inverse();
+
+    // Eigen decomposition of the symmetric F^T F gives the squared stretches.
+    let c = deformation.transpose() * deformation;
+    let (a, b, d) = (c.x_axis.x, c.x_axis.y, c.y_axis.y);
+    let mean = (a + d) * 0.5;
+    let spread = (((a - d) * 0.5).powi(2) + b * b).sqrt();
+    let (most, least) = (mean + spread, (mean - spread).max(0.0));
+    if most <= 0.0 {
+        return None;
+    }
+
+    let cos_tilt = (least / most).sqrt();
+    let least_axis = if b.abs() > f32::EPSILON {
+        vec2(least - d, b)
+    } else if a < d {
+        Vec2::X
+    } else {
+        Vec2::Y
+    };
+    let mut tilt = (deformation * least_axis).normalize_or_zero();
+    if tilt.dot(deformation * Vec2::Y) < 0.0 {
+        tilt = -tilt;
+    }
+    let sin_tilt = (1.0 - cos_tilt * cos_tilt).max(0.0).sqrt();
+    Some(vec3(tilt.x * sin_tilt, tilt.y * sin_tilt, cos_tilt))
+}
+
+// Blue for compressed springs, green at rest and red as the strain approaches `max_strain`.
+// Compression rarely goes far, so it saturates at half the rest length to keep folds visible.
+fn strain_color(strain: f32, max_strain: f32) -> Color {
+    if strain < 0.0 {
+        let t = (-strain / 0.5).clamp(0.0, 1.0);
+        Color::new(0.0, 1.0 - t, t, 1.0)
+    } else {
+        let t = (strain / max_strain).clamp(0.0, 1.0);
+        Color::new(t, 1.0 - t, 0.0, 1.0)
+    }
+}
+
+const COMPONENT_COLORS: [Color; 8] = [SKYBLUE, ORANGE, LIME, PINK, GOLD, VIOLET, RED, BEIGE];
+
+// Heatmap from blue at rest through cyan, green and yellow to red at `t` = 1.
+fn speed_color(t: f32) -> Color {
+    let stops = [BLUE, SKYBLUE, GREEN, YELLOW, RED];
+    let scaled = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (scaled as usize).min(stops.len() - 2);
+    let f = scaled - i as f32;
+    let (a, b) = (stops[i], stops[i + 1]);
+    Color::new(a.r + (b.r - a.r) * f, a.g + (b.g - a.g) * f, a.b + (b.b - a.b) * f, 1.0)
+}
+
+// Glows from a faint dark red through orange to yellow as particles heat up.
+fn heat_color(heat: f32) -> Color {
+    let t = heat.clamp(0.0, 1.0);
+    Color::new(0.5 + 0.5 * t, 0.9 * t * t, 0.2 * t, 0.3 + 0.7 * t)
+}
+
+// A line with a small open head at `to`, sized to the line thickness.
+fn draw_arrow(from: Vec2, to: Vec2, thickness: f32, color: Color) {
+    draw_line(from.x, from.y, to.x, to.y, thickness, color);
+    let Some(direction) = (to - from).try_normalize() else { return };
+    let head = (thickness * 5.0).min(from.distance(to) * 0.5);
+    for side in [-1.0, 1.0] {
+        let tip = to - direction * head + direction.perp() * head * 0.5 * side;
+        draw_line(to.x, to.y, tip.x, tip.y, thickness, color);
+    }
+}
+
+// Faint arrows on a coarse grid across the screen, all showing the current wind, gusts
+// included. They're drawn in screen space behind everything else, and grow with the wind's
+// strength up to most of a grid cell at 2000.
+fn draw_wind_field(wind: Vec2) {
+    const CELL: f32 = 80.0;
+    let arrow = wind.clamp_length_max(2000.0) / 2000.0 * CELL * 0.8;
+    if arrow.length() < 2.0 {
+        return;
+    }
+    let color = Color::new(0.6, 0.8, 1.0, 0.3);
+    let (columns, rows) = ((screen_width() / CELL).ceil() as usize, (screen_height() / CELL).ceil() as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let center = vec2((column as f32 + 0.5) * CELL, (row as f32 + 0.5) * CELL);
+            draw_arrow(center - arrow * 0.5, center + arrow * 0.5, 1.5, color);
         }
+    }
+}
+
+// The settings panel edits whichever cloth is selected; each cloth keeps its own values.
+struct ClothSettings {
+    width: f32,
+    height: f32,
+    spacing_x: f32,
+    spacing_y: f32,
+    start: Vec2,
+    pin_mode: usize,
+    orientation: usize,
+    jitter: f32,
+    seed: f32,
+    shape: usize,
+    diagonals: usize,
+    layers: SpringLayers,
+    stiffness: f32,
+    bend_stiffness: f32,
+    bend_constraints: bool,
+    bend_constraint_stiffness: f32,
+    tear_threshold: f32,
+    structural_tear_scale: f32,
+    shear_tear_scale: f32,
+    bend_tear_scale: f32,
+    absolute_tearing: bool,
+    tear_distance: f32,
+    rate_tearing: bool,
+    tear_rate_weight: f32,
+    rest_length_scale: f32,
+    relaxation: f32,
+    solve_mode: usize,
+    solve_order: usize,
+    iterations: f32,
+    substeps: f32,
+    adaptive_iterations: bool,
+    strain_tolerance: f32,
+    drag: f32,
+    spring_damping: f32,
+    limit_velocity: bool,
+    max_step: f32,
+    limit_stretch: bool,
+    max_stretch: f32,
+    jacobi: bool,
+    parallel: bool,
+    self_collision: bool,
+    collision_distance: f32,
+    // Seconds for gravity to build up from zero after the cloth is built or reset. 0 applies
+    // it in full straight away.
+    gravity_ramp: f32,
+    pressure: f32
+}
 
-        for p in self.particles.iter_mut() {
-            p.update(dt);
+impl ClothSettings {
+    fn new(width: f32, height: f32, spacing: f32, start: Vec2) -> Self {
+        ClothSettings {
+            width,
+            height,
+            spacing_x: spacing,
+            spacing_y: spacing,
+            start,
+            pin_mode: 0,
+            orientation: 0,
+            jitter: 0.0,
+            seed: 0.0,
+            shape: 0,
+            diagonals: 0,
+            layers: SpringLayers::ALL,
+            stiffness: 0.9,
+            bend_stiffness: 0.9,
+            bend_constraints: false,
+            bend_constraint_stiffness: 0.2,
+            tear_threshold: 4.5,
+            structural_tear_scale: 1.0,
+            shear_tear_scale: 1.0,
+            bend_tear_scale: 1.0,
+            absolute_tearing: false,
+            tear_distance: 30.0,
+            rate_tearing: false,
+            tear_rate_weight: 0.02,
+            rest_length_scale: 1.0,
+            relaxation: 0.5,
+            solve_mode: 0,
+            solve_order: 0,
+            iterations: 5.0,
+            substeps: 1.0,
+            adaptive_iterations: false,
+            strain_tolerance: 0.01,
+            drag: 0.0,
+            spring_damping: 0.0,
+            limit_velocity: false,
+            max_step: 20.0,
+            limit_stretch: false,
+            max_stretch: 1.2,
+            jacobi: false,
+            parallel: false,
+            self_collision: false,
+            collision_distance: 8.0,
+            gravity_ramp: 0.0,
+            pressure: 0.0
         }
+    }
 
-        for _ in 0..iterations {
-            self.springs.retain(|s| {
-                let p1 = self.particles[s.p1_idx];
-                let p2 = self.particles[s.p2_idx];
-                let dist = p1.pos.distance(p2.pos);
-                dist < s.rest_length * tear_threshold
-            });
+    fn config(&self) -> ClothConfig {
+        ClothConfig {
+            pin_mode: PinMode::ALL[self.pin_mode],
+            orientation: Orientation::ALL[self.orientation],
+            jitter: self.jitter,
+            seed: self.seed as u64,
+            shape: ClothShape::ALL[self.shape],
+            diagonals: DiagonalMode::ALL[self.diagonals],
+            layers: self.layers,
+            spacing_y: self.spacing_y,
+            ..ClothConfig::new(self.width as usize, self.height as usize, self.spacing_x, self.start)
+        }
+    }
+
+    // The strain the stress view shows in full red. With absolute tearing that's where the
+    // shortest grid springs tear, and longer ones tear at lower strains.
+    fn tear_strain(&self, spacing: Vec2) -> f32 {
+        if self.absolute_tearing {
+            self.tear_distance / (spacing.min_element() * self.rest_length_scale)
+        } else {
+            self.tear_threshold - 1.0
+        }
+    }
+
+    // Fills in the per-cloth fields on top of the scene-wide ones in `scene`.
+    fn sim_params<'a>(&self, scene: &SimParams<'a>) -> SimParams<'a> {
+        SimParams {
+            iterations: self.iterations as usize,
+            substeps: self.substeps as usize,
+            strain_tolerance: self.adaptive_iterations.then_some(self.strain_tolerance),
+            stiffness: self.stiffness,
+            bend_stiffness: self.bend_stiffness,
+            bend_constraint_stiffness: self.bend_constraints.then_some(self.bend_constraint_stiffness),
+            tear_threshold: self.tear_threshold,
+            structural_tear_scale: self.structural_tear_scale,
+            shear_tear_scale: self.shear_tear_scale,
+            bend_tear_scale: self.bend_tear_scale,
+            tear_distance: self.absolute_tearing.then_some(self.tear_distance),
+            tear_rate_weight: self.rate_tearing.then_some(self.tear_rate_weight),
+            rest_length_scale: self.rest_length_scale,
+            relaxation: self.relaxation,
+            solve_mode: SolveMode::ALL[self.solve_mode],
+            solve_order: SolveOrder::ALL[self.solve_order],
+            drag: self.drag,
+            spring_damping: self.spring_damping,
+            max_step: self.limit_velocity.then_some(self.max_step),
+            max_stretch: self.limit_stretch.then_some(self.max_stretch),
+            jacobi: self.jacobi,
+            parallel: self.parallel,
+            self_collision: self.self_collision.then_some(self.collision_distance),
+            pressure: self.pressure,
+            ..*scene
+        }
+    }
+}
 
-            for spring in &self.springs {
-                let p1 = &mut self.particles[spring.p1_idx] as *mut Particle;
-                let p2 = &mut self.particles[spring.p2_idx] as *mut Particle;
+struct SceneCloth {
+    cloth: Cloth,
+    settings: ClothSettings,
+    // The config the cloth was last built from, to notice when the settings ask for a rebuild.
+    config: ClothConfig,
+    // Simulated seconds since the cloth was built or reset, for the gravity ramp.
+    age: f32,
+    // The particle being soft-grabbed this frame and the point it's pulled towards.
+    grab: Option<(usize, Vec2)>,
+    // Whether the scene's held particle belongs to this cloth this frame.
+    holds: bool
+}
 
-                unsafe {
-                    let delta = (*p2).pos - (*p1).pos;
-                    let dist = delta.length();
-                    if dist == 0.0 { continue; }
+impl SceneCloth {
+    fn new(settings: ClothSettings) -> Self {
+        let config = settings.config();
+        SceneCloth { cloth: Cloth::new(&config), settings, config, age: 0.0, grab: None, holds: false }
+    }
 
-                    let diff = (dist - spring.rest_length) / dist;
-                    let correction = delta * 0.5 * diff * stiffness;
-                    
-                    if !(*p1).is_pinned {
-                        (*p1).pos += correction;
-                    }
-                    if !(*p2).is_pinned {
-                        (*p2).pos -= correction;
-                    }
+    // This cloth's parameters, with gravity scaled by how far into the ramp it is.
+    fn sim_params<'a>(&self, scene: &SimParams<'a>) -> SimParams<'a> {
+        let mut params = SimParams { grab: self.grab, held: scene.held.filter(|_| self.holds), ..self.settings.sim_params(scene) };
+        if self.settings.gravity_ramp > 0.0 {
+            params.gravity *= (self.age / self.settings.gravity_ramp).min(1.0);
+        }
+        params
+    }
+}
+
+// Indexes the particles of every cloth in one hash, tagged with the cloth they belong to.
+struct ClothCollider {
+    hash: SpatialHash,
+    owners: Vec<(usize, usize)>
+}
+
+impl ClothCollider {
+    fn new(cell_size: f32) -> Self {
+        ClothCollider { hash: SpatialHash::new(cell_size), owners: Vec::new() }
+    }
+
+    // Pushes apart particles of different cloths closer than `min_dist`. Pairs within one
+    // cloth are left to its own self-collision.
+    fn resolve(&mut self, scene: &mut [SceneCloth], min_dist: f32) {
+        self.owners.clear();
+        for (cloth_idx, entry) in scene.iter().enumerate() {
+            self.owners.extend((0..entry.cloth.particles().len()).map(|i| (cloth_idx, i)));
+        }
+        self.hash.rebuild(scene.iter().flat_map(|e| e.cloth.particles().iter().map(|p| p.pos)));
+
+        for a in 0..self.owners.len() {
+            let (cloth_a, idx_a) = self.owners[a];
+            let pos = scene[cloth_a].cloth.particles()[idx_a].pos;
+            for b in self.hash.query_radius(pos, min_dist) {
+                let (cloth_b, idx_b) = self.owners[b];
+                if cloth_b <= cloth_a {
+                    continue;
+                }
+
+                let (left, right) = scene.split_at_mut(cloth_b);
+                let p1 = &mut left[cloth_a].cloth.particles_mut()[idx_a];
+                let p2 = &mut right[0].cloth.particles_mut()[idx_b];
+                if (p1.is_pinned && p2.is_pinned) || p1.absent || p2.absent {
+                    continue;
+                }
+                let delta = p2.pos - p1.pos;
+                let dist = delta.length();
+                if dist >= min_dist || dist == 0.0 {
+                    continue;
+                }
+
+                let correction = delta * ((min_dist - dist) / dist);
+                if p1.is_pinned {
+                    p2.pos += correction;
+                } else if p2.is_pinned {
+                    p1.pos -= correction;
+                } else {
+                    p1.pos -= correction * 0.5;
+                    p2.pos += correction * 0.5;
                 }
             }
         }
     }
+}
+
+// With cloth collision on, the cloths relax in lockstep so the collision pass can run between
+// their constraint iterations. A cloth with fewer iterations simply sits out the later ones.
+// Returns the most constraint iterations any cloth needed.
+fn step_scene(scene: &mut [SceneCloth], collider: &mut ClothCollider, dt: f32, params: &SimParams) -> usize {
+    let Some(min_dist) = params.cloth_collision.filter(|_| scene.len() > 1) else {
+        return scene.iter_mut()
+            .map(|entry| {
+                let iterations = entry.cloth.update(dt, &entry.sim_params(params));
+                entry.age += dt;
+                iterations
+            })
+            .max()
+            .unwrap_or(0);
+    };
 
-    fn draw(&self) {
-        for spring in &self.springs {
-            let p1 = self.particles[spring.p1_idx];
-            let p2 = self.particles[spring.p2_idx];
-            draw_line(p1.pos.x, p1.pos.y, p2.pos.x, p2.pos.y, 1.0, WHITE);
+    // Cloths stepped together have to share their substeps, so they all take the largest
+    // count any of them asks for.
+    let cloth_params: Vec<SimParams> = scene.iter().map(|e| e.sim_params(params)).collect();
+    let substeps = cloth_params.iter().map(|p| p.substeps.max(1)).max().unwrap_or(1);
+    let dt = dt / substeps as f32;
+    let mut most_iterations = 0;
+    for _ in 0..substeps {
+        for (entry, cloth_params) in scene.iter_mut().zip(&cloth_params) {
+            entry.cloth.integrate(dt, cloth_params);
+            entry.age += dt;
         }
-        for p in &self.particles {
-            if p.is_pinned {
-                draw_circle(p.pos.x, p.pos.y, 3.0, RED);
-            } else {
-                draw_circle(p.pos.x, p.pos.y, 2.0, BLUE);
+        // Each cloth stops relaxing once it has run its iterations or converged, while the
+        // others carry on.
+        let mut done: Vec<bool> = cloth_params.iter().map(|p| p.iterations == 0).collect();
+        let mut iterations = 0;
+        while done.contains(&false) {
+            for ((entry, cloth_params), done) in scene.iter_mut().zip(&cloth_params).zip(&mut done) {
+                if !*done {
+                    entry.cloth.relax(dt, cloth_params);
+                    *done = iterations + 1 >= cloth_params.iterations || entry.cloth.converged(cloth_params);
+                }
             }
+            collider.resolve(scene, min_dist);
+            iterations += 1;
+        }
+        for (entry, cloth_params) in scene.iter_mut().zip(&cloth_params) {
+            entry.cloth.finish_step(cloth_params);
         }
+        most_iterations = most_iterations.max(iterations);
     }
+    most_iterations
 }
 
-fn distance_point_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
-    let ab = b - a;
-    let ap = p - a;
-    let len_sq = ab.length_squared();
+// Picks the closest particle across all cloths as (cloth index, particle index).
+fn nearest_particle(scene: &[SceneCloth], hashes: &[SpatialHash], pos: Vec2, max_dist: f32) -> Option<(usize, usize)> {
+    scene.iter().zip(hashes).enumerate()
+        .flat_map(|(cloth_idx, (entry, hash))| {
+            // The hash may be a frame behind if the cloth was just replaced.
+            hash.query_radius(pos, max_dist).into_iter()
+                .filter_map(move |idx| Some((cloth_idx, idx, entry.cloth.particles().get(idx)?)))
+        })
+        .filter(|(_, _, p)| !p.absent)
+        .map(|(cloth_idx, idx, p)| (cloth_idx, idx, p.pos.distance_squared(pos)))
+        .filter(|&(_, _, dist_sq)| dist_sq < max_dist * max_dist)
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(cloth_idx, idx, _)| (cloth_idx, idx))
+}
+
+// Like `nearest_particle`, but only considers pinned particles.
+fn nearest_pinned(scene: &[SceneCloth], pos: Vec2, max_dist: f32) -> Option<(usize, usize)> {
+    nearest_where(scene, pos, max_dist, |p| p.is_pinned)
+}
 
-    if len_sq == 0.0 {
-        return p.distance(a);
+// Like `nearest_particle`, but only considers the particles `keep` accepts. Searches every
+// particle rather than the hashes, which can't filter. Absent particles never count.
+fn nearest_where(scene: &[SceneCloth], pos: Vec2, max_dist: f32, keep: impl Fn(&Particle) -> bool + Copy) -> Option<(usize, usize)> {
+    scene.iter().enumerate()
+        .flat_map(|(cloth_idx, entry)| {
+            entry.cloth.particles().iter().enumerate()
+                .filter(move |(_, p)| !p.absent && keep(p))
+                .map(move |(idx, p)| (cloth_idx, idx, p.pos.distance_squared(pos)))
+        })
+        .filter(|&(_, _, dist_sq)| dist_sq < max_dist * max_dist)
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(cloth_idx, idx, _)| (cloth_idx, idx))
+}
+
+// Angle 0 is straight down and positive angles tip gravity towards +x. Built from sin/cos
+// directly so the default stays exactly (0, magnitude).
+fn gravity_vector(magnitude: f32, angle: f32) -> Vec2 {
+    vec2(angle.to_radians().sin(), angle.to_radians().cos()) * magnitude
+}
+
+// A text field with its label to the right, like `Ui::input_text`, but returning whether it
+// took keyboard input this frame, which only happens while it has focus.
+fn text_field(ui: &mut Ui, id: Id, label: &str, text: &mut String) -> bool {
+    let typed = widgets::Editbox::new(id, vec2(110., 19.)).ui(ui, text);
+    ui.same_line(0.);
+    ui.label(None, label);
+    typed
+}
+
+// Reads a typed cloth size like "50x30" or "50 30", rejecting anything outside the sliders' range.
+fn parse_size(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.split(|c: char| c == 'x' || c == 'X' || c == '*' || c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<usize>().ok().filter(|n| (MIN_CLOTH_SIZE..=MAX_CLOTH_SIZE).contains(n)));
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(width), Some(height), None) => width.zip(height),
+        _ => None
     }
+}
 
-    let t = (ap.dot(ab) / len_sq).clamp(0.0, 1.0);
+// The spring of `cloth` passing closest to `pos`, if any is within `max_dist`.
+fn nearest_spring(cloth: &Cloth, pos: Vec2, max_dist: f32) -> Option<usize> {
+    let particles = cloth.particles();
+    cloth.springs().iter().enumerate()
+        .map(|(i, s)| (i, distance_point_to_segment(pos, particles[s.p1_idx].pos, particles[s.p2_idx].pos)))
+        .filter(|&(_, dist)| dist < max_dist)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
 
-    let closest_point = a + t * ab;
-    p.distance(closest_point)
+// 2D camera over the world, which matches screen coordinates at zoom 1 with no panning.
+struct View {
+    center: Vec2,
+    zoom: f32
+}
+
+impl View {
+    fn new() -> Self {
+        View { center: vec2(screen_width(), screen_height()) * 0.5, zoom: 1.0 }
+    }
+
+    fn camera(&self) -> Camera2D {
+        Camera2D {
+            target: self.center,
+            zoom: vec2(2.0 / screen_width(), -2.0 / screen_height()) * self.zoom,
+            ..Default::default()
+        }
+    }
+
+    fn screen_to_world(&self, screen_pos: Vec2) -> Vec2 {
+        self.camera().screen_to_world(screen_pos)
+    }
+
+    fn world_to_screen(&self, world_pos: Vec2) -> Vec2 {
+        self.camera().world_to_screen(world_pos)
+    }
+
+    // Keeps the world point under `screen_pos` fixed while zooming.
+    fn zoom_at(&mut self, screen_pos: Vec2, factor: f32) {
+        let before = self.screen_to_world(screen_pos);
+        self.zoom = (self.zoom * factor).clamp(0.1, 10.0);
+        let after = self.screen_to_world(screen_pos);
+        self.center += before - after;
+    }
+
+    fn pan(&mut self, from_screen: Vec2, to_screen: Vec2) {
+        self.center += self.screen_to_world(from_screen) - self.screen_to_world(to_screen);
+    }
+}
+
+// Captures the rendered screen into numbered PNGs. Each frame is written and closed before
+// the next one starts, so stopping never leaves a half-written file behind.
+struct Recorder {
+    dir: String,
+    frame: usize,
+    active: bool
+}
+
+impl Recorder {
+    fn new(dir: &str) -> Self {
+        Recorder { dir: dir.to_owned(), frame: 0, active: false }
+    }
+
+    fn start(&mut self) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        self.frame = 0;
+        self.active = true;
+        Ok(())
+    }
+
+    // Returns how many frames were written.
+    fn stop(&mut self) -> usize {
+        self.active = false;
+        self.frame
+    }
+
+    fn capture(&mut self) {
+        get_screen_data().export_png(&format!("{}/frame_{:05}.png", self.dir, self.frame));
+        self.frame += 1;
+    }
+}
+
+// Dims the whole window and lists `BINDINGS` in two columns over it.
+fn draw_help() {
+    let font_size = 18.0;
+    let line_height = 22.0;
+    let key_width = 230.0;
+    draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.7));
+    let x = (screen_width() - key_width * 2.0).max(0.0) * 0.5;
+    let y = ((screen_height() - BINDINGS.len() as f32 * line_height) * 0.5).max(40.0);
+    for (i, (keys, action)) in BINDINGS.iter().enumerate() {
+        let line_y = y + line_height * i as f32;
+        draw_text(keys, x, line_y, font_size, YELLOW);
+        draw_text(action, x + key_width, line_y, font_size, WHITE);
+    }
+}
+
+// Stacks text lines in a shaded box in the top-right corner of the screen.
+fn draw_overlay(lines: &[String]) {
+    let font_size = 18.0;
+    let line_height = 20.0;
+    let box_width = 260.0;
+    let x = screen_width() - box_width - 10.0;
+    let y = 40.0;
+    draw_rectangle(x, y, box_width, lines.len() as f32 * line_height + 10.0, Color::new(0.0, 0.0, 0.0, 0.6));
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, x + 8.0, y + line_height * (i as f32 + 1.0), font_size, WHITE);
+    }
+}
+
+// Reads `--width N` and `--height N`, keeping the default for anything missing or invalid.
+fn window_size_from_args(args: impl IntoIterator<Item = String>) -> (i32, i32) {
+    let mut size = (1200, 800);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--width" => &mut size.0,
+            "--height" => &mut size.1,
+            _ => continue
+        };
+        match args.next().map(|value| value.parse::<i32>()) {
+            Some(Ok(value)) if value > 0 => *target = value,
+            _ => eprintln!("ignoring {}: expected a positive number of pixels", arg)
+        }
+    }
+    size
+}
+
+// Reads the file named after `flag`, like `--replay FILE` for a recording to play back as soon
+// as the window opens or `--texture FILE` for the image to map onto the cloth.
+fn path_from_args(args: impl IntoIterator<Item = String>, flag: &str) -> Option<String> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+    }
+    None
 }
 
 fn window_conf() -> Conf {
+    let (window_width, window_height) = window_size_from_args(std::env::args().skip(1));
     Conf {
         window_title: "2D Cloth Simulator".to_owned(),
-        window_width: 1200,
-        window_height: 800,
+        window_width,
+        window_height,
+        // Multisampling smooths the edges of the wireframe lines, which are otherwise jagged
+        // at any thickness once zoomed in.
+        sample_count: 4,
         ..Default::default()
     }
 }
 
 #[macroquad::main(window_conf)]
 async fn main() {
-    let mut cloth_width: f32 = 40.;
-    let mut cloth_height: f32 = 25.;
-    let cloth_spacing: f32 = 15.0;
-    let cloth_start_pos: Vec2 = vec2(300.0, 50.0);
+    let saved_settings = SavedSettings::load(SETTINGS_PATH);
+    let cloth_spacing: f32 = saved_settings.spacing_x;
+    // Centered horizontally on whatever size the window actually opened at.
+    let cloth_start_pos: Vec2 = vec2(((screen_width() - saved_settings.cloth_width * cloth_spacing) / 2.0).max(0.0), 50.0);
 
-    let mut last_width = cloth_width;
-    let mut last_height = cloth_height;
+    let mut scene = vec![SceneCloth::new(saved_settings.cloth_settings(cloth_start_pos))];
+    let mut selected_cloth = 0;
+    let mut obstacles: Vec<CircleObstacle> = Vec::new();
+    let mut rect_obstacles: Vec<RectObstacle> = Vec::new();
+    let mut rect_start: Option<Vec2> = None;
+    let mut selected_particle: Option<(usize, usize)> = None;
+    let mut selected_obstacle_idx: Option<usize> = None;
+    let mut dragged_anchor: Option<(usize, usize)> = None;
+    let mut flicked_particle: Option<(usize, usize)> = None;
+    // Pinned particles driven along a path: cloth, particle, path and when it started.
+    let mut motion_paths: Vec<(usize, usize, MotionPath, f64)> = Vec::new();
+    let mut particle_hashes: Vec<SpatialHash> = Vec::new();
+    let mut cloth_collider = ClothCollider::new(20.0);
+    let mut brush_particles: Vec<(usize, f32)> = Vec::new();
+    let mut edit_mode = false;
+    // The first particle clicked in edit mode, waiting for a second one to join it to.
+    let mut edit_anchor: Option<(usize, usize)> = None;
+    let mut last_mouse_pos = Vec2::ZERO;
+    // Springs severed by the current or last cut stroke (or restored by the last undo), with
+    // where and when to show the count.
+    let mut cut_feedback: Option<(usize, &str, Vec2, f64)> = None;
+    let mut view = View::new();
+    let mut last_mouse_screen = Vec2::ZERO;
 
-    let mut cloth = Cloth::new(cloth_width as usize, cloth_height as usize, cloth_spacing, cloth_start_pos.x, cloth_start_pos.y);
-    let mut selected_particle_idx: Option<usize> = None;
+    let mut gravity_magnitude = saved_settings.gravity;
+    let mut gravity_angle: f32 = saved_settings.gravity_angle;
+    // The gravity a flip started from and when, while it's easing towards the new direction.
+    let mut gravity_flip: Option<(Vec2, f64)> = None;
+    let mut floor_enabled = false;
+    let mut floor_y = screen_height() - 20.0;
+    let mut walls_enabled = false;
+    let mut collision = CollisionParams::default();
+    let mut cut_radius = saved_settings.cut_radius;
+    let mut hard_cut = false;
+    let mut wind_angle = 0.0;
+    let mut wind_strength = 0.0;
+    let mut gust_amplitude = 200.0;
+    let mut gust_frequency = 0.5;
+    let mut aerodynamic_wind = false;
+    let mut show_wind = false;
+    let mut show_bounds = false;
+    let mut cloth_collision = false;
+    let mut cloth_collision_distance = 8.0;
+    let mut obstacle_radius = 60.0;
+    let mut grab_radius = 0.0;
+    let mut soft_grab = false;
+    let mut drag_pinned = true;
+    let mut grab_stiffness = 5000.0;
+    let mut limit_drag = false;
+    let mut max_drag_step = 4.0;
+    let mut flick_strength = 0.1;
+    let mut burst_radius = 100.0;
+    let mut burst_strength = 100000.0;
+    let mut heat_radius = 40.0;
+    let mut heat_rate = 3.0;
+    let mut heat_weakening = 1.0;
+    let mut heat_diffusion = 2.0;
+    let mut heat_decay = 0.5;
+    let mut reinforce_rate = 2.0;
+    let mut path_shape = 0;
+    let mut path_amplitude = 100.0;
+    let mut path_frequency = 0.5;
+    let mut attractor_enabled = false;
+    let mut attractor_strength = 100000.0;
+    let mut file_status = String::new();
+    let mut size_text = String::new();
+    let size_input = hash!("size input");
+    let mut memory_cap_mb = 64.0;
+    let mut paused = false;
+    let mut accumulator = 0.0;
+    let mut draw_mode = 0;
+    let mut render_preset = 0;
+    let mut last_render_preset = render_preset;
+    let mut render = RenderConfig::preset(RenderPreset::Default);
+    let mut max_draw_speed = 600.0;
+    let mut light_angle: f32 = 0.0;
+    let mut light_elevation: f32 = 45.0;
+    // An image passed on the command line has to load; the default one is optional.
+    let texture_path = path_from_args(std::env::args().skip(1), "--texture");
+    let mut cloth_texture = match &texture_path {
+        Some(path) => load_texture(path).await
+            .map_err(|e| eprintln!("failed to load the texture {}: {}", path, e))
+            .ok(),
+        None => load_texture(TEXTURE_PATH).await.ok()
+    };
+    let mut texture_text = texture_path.unwrap_or_else(|| TEXTURE_PATH.to_owned());
+    let texture_input = hash!("texture input");
+    let mut typing = false;
+    let mut texture_request: Option<String> = None;
+    // Without the material the shaded mode falls back to the wireframe.
+    let cloth_material = shading::load_cloth_material()
+        .map_err(|e| eprintln!("failed to build the cloth shader: {}", e))
+        .ok();
+    let mut show_stats = false;
+    let mut show_help = false;
+    let mut iterations_used = 0;
+    let mut fullscreen = false;
+    let mut snapshot: Option<Vec<ClothState>> = None;
+    let mut recorder = Recorder::new(RECORD_DIR);
+    let mut input = Input::default();
+    let mut input_recording: Option<InputRecording> = None;
+    let mut replay: Option<Replay> = None;
+    let mut replay_request = path_from_args(std::env::args().skip(1), "--replay");
+    // Every panel option that changes what the sim does but isn't in `SavedSettings`. A
+    // recording or replay puts them back to these startup values and hides them until it ends,
+    // so none of them can differ between the two.
+    macro_rules! unrecorded_options {
+        () => {
+            (
+                (edit_mode, hard_cut, grab_radius, drag_pinned, soft_grab, grab_stiffness, limit_drag, max_drag_step),
+                (flick_strength, burst_radius, burst_strength, heat_radius, heat_rate, reinforce_rate),
+                (path_shape, path_amplitude, path_frequency, attractor_enabled, attractor_strength, obstacle_radius),
+                (floor_enabled, floor_y, walls_enabled, collision, gravity_flip, memory_cap_mb),
+                (wind_angle, wind_strength, gust_amplitude, gust_frequency, aerodynamic_wind),
+                (heat_weakening, heat_diffusion, heat_decay, cloth_collision, cloth_collision_distance)
+            )
+        };
+    }
+    let default_options = unrecorded_options!();
 
-    let mut stiffness = 0.9;
-    let mut tear_threshold = 4.5;
-    let mut gravity_y = 980.0;
-    let mut iterations = 5.0;
-    let mut cut_radius = 10.0;
+    // Keep the window open long enough to store the settings when it's closed.
+    prevent_quit();
 
     loop {
         clear_background(BLACK);
 
-        let dt = get_frame_time().min(1.0 / 30.0);
+        if is_quit_requested() {
+            if let Some(Err(e)) = input_recording.take().map(InputRecording::finish) {
+                eprintln!("failed to write {}: {}", REPLAY_PATH, e);
+            }
+            let settings = SavedSettings::new(&scene[selected_cloth].settings, gravity_magnitude, gravity_angle, cut_radius);
+            if let Err(e) = settings.save(SETTINGS_PATH) {
+                eprintln!("failed to save {}: {}", SETTINGS_PATH, e);
+            }
+            break;
+        }
+        let mut save_settings = false;
+        let mut toggle_input_recording = false;
 
-        widgets::Window::new(hash!(), vec2(10., 40.), vec2(280., 260.))
+        let mut rebuild_cloth = false;
+        let mut step_requested = false;
+        let mut add_cloth = false;
+        let mut remove_cloth = false;
+        let mut settle_cloth = false;
+        let mut flip_gravity = false;
+        let mut text_typed = false;
+
+        let counts = format!("{} particles, {} springs", scene[selected_cloth].cloth.particles().len(), scene[selected_cloth].cloth.springs().len());
+        let cloth_labels: Vec<String> = (1..=scene.len()).map(|i| format!("Cloth {}", i)).collect();
+        let cloth_label_refs: Vec<&str> = cloth_labels.iter().map(String::as_str).collect();
+
+        widgets::Window::new(hash!(), vec2(10., 40.), vec2(280., screen_height() - 50.))
             .label("Simulation Configurations")
             .ui(&mut root_ui(), |ui| {
-                ui.label(None, "Cloth Size:");
-                ui.slider(hash!(), &format!("Width ({})", cloth_width as usize), 4. ..64., &mut cloth_width);
-                ui.slider(hash!(), &format!("Height ({})", cloth_height as usize), 4. ..64., &mut cloth_height);
-                ui.slider(hash!(), "Cut radius", 10. ..50.0, &mut cut_radius);
-                
-                ui.separator();
-                ui.slider(hash!(), "Gravity", 0. ..2000.0, &mut gravity_y);
-                ui.slider(hash!(), "Stiffness", 0.1..1.0, &mut stiffness);
-                ui.slider(hash!(), "Tear threshold", 1.1..10.0, &mut tear_threshold);
-                ui.slider(hash!(), "Iterations", 1. ..20., &mut iterations);
-                ui.label(None, &format!("(Current: {})", iterations as usize));
+                // A recording only captures the saved settings, so while one is made the panel
+                // offers nothing else, and while one plays back it offers nothing at all.
+                if replay.is_some() {
+                    ui.label(None, &file_status);
+                    return;
+                }
+                if input_recording.is_some() {
+                    let settings = &mut scene[selected_cloth].settings;
+                    ui.slider(hash!(), &format!("Width ({})", settings.width as usize), 4. ..64., &mut settings.width);
+                    ui.slider(hash!(), &format!("Height ({})", settings.height as usize), 4. ..64., &mut settings.height);
+                    ui.slider(hash!(), "Spacing X", 5. ..40., &mut settings.spacing_x);
+                    ui.slider(hash!(), "Spacing Y", 5. ..40., &mut settings.spacing_y);
+                    ui.slider(hash!(), "Stiffness", 0.1..1.0, &mut settings.stiffness);
+                    ui.slider(hash!(), "Tear threshold", 1.1..10.0, &mut settings.tear_threshold);
+                    ui.slider(hash!(), "Iterations", 1. ..20., &mut settings.iterations);
+                    ui.slider(hash!(), "Gravity", 0. ..2000.0, &mut gravity_magnitude);
+                    ui.slider(hash!(), "Gravity angle (deg)", -180. ..180., &mut gravity_angle);
+                    ui.slider(hash!(), "Cut radius", 10. ..50.0, &mut cut_radius);
+                    if ui.button(None, "Stop Input Recording") {
+                        toggle_input_recording = true;
+                    }
+                    ui.label(None, &file_status);
+                    return;
+                }
+                ui.combo_box(hash!(), "Selected", &cloth_label_refs, &mut selected_cloth);
+                if ui.button(None, "Add Cloth") {
+                    add_cloth = true;
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Remove Cloth") {
+                    remove_cloth = true;
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Settle") {
+                    settle_cloth = true;
+                }
+                let settings = &mut scene[selected_cloth].settings;
+
+                widgets::TreeNode::new(hash!(), "Cloth").init_unfolded().ui(ui, |ui| {
+                    ui.combo_box(hash!(), "Shape", &ClothShape::LABELS, &mut settings.shape);
+                    ui.slider(hash!(), &format!("Width ({})", settings.width as usize), 4. ..64., &mut settings.width);
+                    ui.slider(hash!(), &format!("Height ({})", settings.height as usize), 4. ..64., &mut settings.height);
+                    // Typed sizes go through the same range as the sliders, so a typo can't
+                    // build a cloth big enough to freeze the app.
+                    text_typed |= text_field(ui, size_input, "Size (WxH)", &mut size_text);
+                    ui.same_line(0.);
+                    if ui.button(None, "Apply") {
+                        match parse_size(&size_text) {
+                            Some((width, height)) => {
+                                settings.width = width as f32;
+                                settings.height = height as f32;
+                            }
+                            None => file_status = format!("Size must be two numbers from {} to {}, like 50x30", MIN_CLOTH_SIZE, MAX_CLOTH_SIZE)
+                        }
+                    }
+                    ui.label(None, &counts);
+                    ui.slider(hash!(), "Memory cap (MB)", 1. ..1024., &mut memory_cap_mb);
+                    ui.slider(hash!(), "Spacing X", 5. ..40., &mut settings.spacing_x);
+                    ui.slider(hash!(), "Spacing Y", 5. ..40., &mut settings.spacing_y);
+                    ui.combo_box(hash!(), "Pinning", &PinMode::LABELS, &mut settings.pin_mode);
+                    ui.combo_box(hash!(), "Orientation", &Orientation::LABELS, &mut settings.orientation);
+                    ui.slider(hash!(), "Jitter", 0. ..5., &mut settings.jitter);
+                    ui.slider(hash!(), "Gravity ramp (s)", 0. ..5., &mut settings.gravity_ramp);
+                    ui.slider(hash!(), "Pressure", 0. ..500., &mut settings.pressure);
+                    ui.slider(hash!(), &format!("Seed ({})", settings.seed as u64), 0. ..1000., &mut settings.seed);
+                    ui.checkbox(hash!(), "Structural springs", &mut settings.layers.structural);
+                    ui.checkbox(hash!(), "Shear springs", &mut settings.layers.shear);
+                    ui.combo_box(hash!(), "Diagonals", &DiagonalMode::LABELS, &mut settings.diagonals);
+                    ui.checkbox(hash!(), "Bend springs", &mut settings.layers.bend);
+                });
+
+                widgets::TreeNode::new(hash!(), "Tools").init_unfolded().ui(ui, |ui| {
+                    ui.checkbox(hash!(), "Edit mode", &mut edit_mode);
+                    ui.slider(hash!(), "Cut radius", 10. ..50.0, &mut cut_radius);
+                    ui.checkbox(hash!(), "Hard cut", &mut hard_cut);
+                    ui.slider(hash!(), "Grab radius", 0. ..150.0, &mut grab_radius);
+                    ui.checkbox(hash!(), "Drag pinned", &mut drag_pinned);
+                    ui.checkbox(hash!(), "Soft grab", &mut soft_grab);
+                    ui.slider(hash!(), "Grab stiffness", 500. ..40000., &mut grab_stiffness);
+                    ui.checkbox(hash!(), "Limit drag", &mut limit_drag);
+                    ui.slider(hash!(), "Drag limit (px/substep)", 0.5..20., &mut max_drag_step);
+                    ui.slider(hash!(), "Flick strength", 0.01..0.5, &mut flick_strength);
+                    ui.slider(hash!(), "Burst radius", 20. ..300., &mut burst_radius);
+                    ui.slider(hash!(), "Burst strength", 0. ..500000., &mut burst_strength);
+                    ui.slider(hash!(), "Heat radius", 10. ..150., &mut heat_radius);
+                    ui.slider(hash!(), "Heat rate", 0.5..10., &mut heat_rate);
+                    ui.slider(hash!(), "Reinforce rate", 0.5..10., &mut reinforce_rate);
+                    ui.combo_box(hash!(), "Motion path", &PathShape::LABELS, &mut path_shape);
+                    ui.slider(hash!(), "Path amplitude", 10. ..300., &mut path_amplitude);
+                    ui.slider(hash!(), "Path frequency", 0.05..3., &mut path_frequency);
+                    if ui.button(None, "Clear Paths") {
+                        motion_paths.clear();
+                    }
+                    ui.checkbox(hash!(), "Attractor", &mut attractor_enabled);
+                    ui.slider(hash!(), "Attractor strength", 0. ..500000., &mut attractor_strength);
+                    ui.slider(hash!(), "Obstacle radius", 10. ..200., &mut obstacle_radius);
+                    if ui.button(None, "Clear Obstacles") {
+                        obstacles.clear();
+                        rect_obstacles.clear();
+                        selected_obstacle_idx = None;
+                    }
+                });
+
+                widgets::TreeNode::new(hash!(), "Solver").init_unfolded().ui(ui, |ui| {
+                    ui.combo_box(hash!(), "Constraints", &SolveMode::LABELS, &mut settings.solve_mode);
+                    ui.combo_box(hash!(), "Spring order", &SolveOrder::LABELS, &mut settings.solve_order);
+                    ui.slider(hash!(), "Stiffness", 0.1..1.0, &mut settings.stiffness);
+                    ui.slider(hash!(), "Bend stiffness", 0.0..1.0, &mut settings.bend_stiffness);
+                    ui.checkbox(hash!(), "Bend constraints", &mut settings.bend_constraints);
+                    ui.slider(hash!(), "Bend constraint stiffness", 0.0..1.0, &mut settings.bend_constraint_stiffness);
+                    ui.slider(hash!(), "Rest length scale", 0.5..1.5, &mut settings.rest_length_scale);
+                    ui.slider(hash!(), "Iterations", 1. ..20., &mut settings.iterations);
+                    ui.label(None, &format!("(Current: {})", settings.iterations as usize));
+                    ui.slider(hash!(), &format!("Substeps ({})", settings.substeps as usize), 1. ..8., &mut settings.substeps);
+                    ui.checkbox(hash!(), "Adaptive iterations", &mut settings.adaptive_iterations);
+                    ui.slider(hash!(), "Strain tolerance", 0.001..0.1, &mut settings.strain_tolerance);
+                    ui.slider(hash!(), "Relaxation", 0.1..1.0, &mut settings.relaxation);
+                    ui.slider(hash!(), "Drag", 0. ..0.1, &mut settings.drag);
+                    ui.slider(hash!(), "Spring damping", 0. ..1., &mut settings.spring_damping);
+                    ui.checkbox(hash!(), "Limit velocity", &mut settings.limit_velocity);
+                    ui.slider(hash!(), "Max step (px)", 1. ..50., &mut settings.max_step);
+                    ui.checkbox(hash!(), "Limit stretch", &mut settings.limit_stretch);
+                    ui.slider(hash!(), "Max stretch", 1. ..3., &mut settings.max_stretch);
+                    ui.checkbox(hash!(), "Jacobi mode", &mut settings.jacobi);
+                    ui.checkbox(hash!(), "Parallel solver", &mut settings.parallel);
+                    ui.checkbox(hash!(), "Self collision", &mut settings.self_collision);
+                    ui.slider(hash!(), "Collision distance", 2. ..15., &mut settings.collision_distance);
+                });
+
+                widgets::TreeNode::new(hash!(), "Tearing").ui(ui, |ui| {
+                    ui.slider(hash!(), "Tear threshold", 1.1..10.0, &mut settings.tear_threshold);
+                    ui.label(None, "Per-kind multipliers:");
+                    ui.slider(hash!(), "Structural", 0.25..2.0, &mut settings.structural_tear_scale);
+                    ui.slider(hash!(), "Shear", 0.25..2.0, &mut settings.shear_tear_scale);
+                    ui.slider(hash!(), "Bend", 0.25..2.0, &mut settings.bend_tear_scale);
+                    ui.separator();
+                    ui.checkbox(hash!(), "Absolute tearing", &mut settings.absolute_tearing);
+                    ui.slider(hash!(), "Tear distance (px)", 1. ..150., &mut settings.tear_distance);
+                    ui.separator();
+                    ui.checkbox(hash!(), "Rate tearing", &mut settings.rate_tearing);
+                    ui.slider(hash!(), "Rate weight (s)", 0.001..0.1, &mut settings.tear_rate_weight);
+                });
+
+                widgets::TreeNode::new(hash!(), "Environment").ui(ui, |ui| {
+                    ui.slider(hash!(), "Gravity", 0. ..2000.0, &mut gravity_magnitude);
+                    ui.slider(hash!(), "Gravity angle (deg)", -180. ..180., &mut gravity_angle);
+                    // The same gravity by component, for a sideways pull or an upward one for
+                    // buoyancy. Both pairs of sliders edit the magnitude and angle underneath,
+                    // which are what gets saved and recorded.
+                    let mut gravity = gravity_vector(gravity_magnitude, gravity_angle);
+                    let before = gravity;
+                    ui.slider(hash!(), "Gravity X", -2000. ..2000., &mut gravity.x);
+                    ui.slider(hash!(), "Gravity Y", -2000. ..2000., &mut gravity.y);
+                    if ui.button(None, "Flip Gravity") {
+                        flip_gravity = true;
+                    }
+                    if gravity != before {
+                        gravity_magnitude = gravity.length().min(2000.0);
+                        // Zero gravity has no direction, so the angle is kept for when it returns.
+                        if gravity != Vec2::ZERO {
+                            gravity_angle = gravity.x.atan2(gravity.y).to_degrees();
+                        }
+                    }
+                    ui.separator();
+                    ui.checkbox(hash!(), "Floor", &mut floor_enabled);
+                    ui.slider(hash!(), "Floor height", 0. ..screen_height(), &mut floor_y);
+                    ui.checkbox(hash!(), "Walls", &mut walls_enabled);
+                    ui.slider(hash!(), "Restitution", 0. ..1., &mut collision.restitution);
+                    ui.slider(hash!(), "Friction", 0. ..1., &mut collision.friction);
+                    ui.separator();
+                    ui.label(None, "Wind:");
+                    ui.slider(hash!(), "Direction (deg)", 0. ..360., &mut wind_angle);
+                    ui.slider(hash!(), "Strength", 0. ..2000., &mut wind_strength);
+                    ui.slider(hash!(), "Gust amplitude", 0. ..1000., &mut gust_amplitude);
+                    ui.slider(hash!(), "Gust frequency", 0. ..5., &mut gust_frequency);
+                    ui.checkbox(hash!(), "Aerodynamic wind", &mut aerodynamic_wind);
+                    ui.checkbox(hash!(), "Show wind", &mut show_wind);
+                    ui.separator();
+                    ui.label(None, "Heat:");
+                    ui.slider(hash!(), "Weakening", 0. ..1., &mut heat_weakening);
+                    ui.slider(hash!(), "Diffusion", 0. ..10., &mut heat_diffusion);
+                    ui.slider(hash!(), "Decay", 0. ..3., &mut heat_decay);
+                    ui.separator();
+                    ui.checkbox(hash!(), "Cloth collision", &mut cloth_collision);
+                    ui.slider(hash!(), "Cloth collision distance", 2. ..15., &mut cloth_collision_distance);
+                });
                 ui.separator();
 
+                ui.combo_box(hash!(), "Draw mode", &DrawMode::LABELS, &mut draw_mode);
+                widgets::TreeNode::new(hash!(), "Appearance").ui(ui, |ui| {
+                    ui.combo_box(hash!(), "Preset", &RenderPreset::LABELS, &mut render_preset);
+                    ui.slider(hash!(), "Particle radius", 0. ..8., &mut render.particle_radius);
+                    ui.slider(hash!(), "Line thickness", 0.5..8., &mut render.line_thickness);
+                    ui.checkbox(hash!(), "Show bounds", &mut show_bounds);
+                    ui.slider(hash!(), "Max speed (px/s)", 50. ..3000., &mut max_draw_speed);
+                    ui.slider(hash!(), "Light angle (deg)", 0. ..360., &mut light_angle);
+                    ui.slider(hash!(), "Light elevation (deg)", 0. ..90., &mut light_elevation);
+                    ui.slider(hash!(), "Rim light", 0. ..1., &mut render.rim_strength);
+                    ui.checkbox(hash!(), "Smooth shading", &mut render.smooth_shading);
+                    for (label, color) in [
+                        ("Pinned", &mut render.pinned_color),
+                        ("Free", &mut render.free_color),
+                        ("Lines", &mut render.line_color),
+                        ("Torn", &mut render.torn_color),
+                        ("Reinforced", &mut render.reinforced_color),
+                        ("Fabric", &mut render.fabric_color)
+                    ] {
+                        ui.label(None, &format!("{} color:", label));
+                        ui.slider(hash!(label, "r"), "R", 0. ..1., &mut color.r);
+                        ui.slider(hash!(label, "g"), "G", 0. ..1., &mut color.g);
+                        ui.slider(hash!(label, "b"), "B", 0. ..1., &mut color.b);
+                    }
+                });
+                ui.checkbox(hash!(), "Paused", &mut paused);
+                if ui.button(None, "Step") {
+                    step_requested = true;
+                }
+
                 if ui.button(None, "Reset Cloth") {
-                    cloth = Cloth::new(cloth_width as usize, cloth_height as usize, cloth_spacing, cloth_start_pos.x, cloth_start_pos.y);
-                    selected_particle_idx = None;
+                    rebuild_cloth = true;
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Reset View") {
+                    view = View::new();
+                }
+                let cloth = &mut scene[selected_cloth].cloth;
+                if ui.button(None, "Save") {
+                    file_status = match cloth.save(SAVE_PATH) {
+                        Ok(()) => format!("Saved to {}", SAVE_PATH),
+                        Err(e) => format!("Save failed: {}", e)
+                    };
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Load") {
+                    file_status = match Cloth::load(SAVE_PATH) {
+                        Ok(loaded) => {
+                            *cloth = loaded;
+                            selected_particle = None;
+                            edit_anchor = None;
+                            brush_particles.clear();
+                            motion_paths.retain(|&(cloth_idx, ..)| cloth_idx != selected_cloth);
+                            format!("Loaded {}", SAVE_PATH)
+                        }
+                        Err(e) => format!("Load failed: {}", e)
+                    };
+                }
+                if ui.button(None, if recorder.active { "Stop Recording" } else { "Record" }) {
+                    file_status = if recorder.active {
+                        format!("Recorded {} frames to {}/", recorder.stop(), RECORD_DIR)
+                    } else {
+                        match recorder.start() {
+                            Ok(()) => format!("Recording to {}/", RECORD_DIR),
+                            Err(e) => format!("Recording failed: {}", e)
+                        }
+                    };
+                }
+                if ui.button(None, if input_recording.is_some() { "Stop Input Recording" } else { "Record Input" }) {
+                    toggle_input_recording = true;
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Replay Input") {
+                    replay_request = Some(REPLAY_PATH.to_owned());
+                }
+                if ui.button(None, "Save Settings") {
+                    save_settings = true;
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Export OBJ") {
+                    file_status = match cloth.export_obj(OBJ_PATH) {
+                        Ok(()) => format!("Exported {}", OBJ_PATH),
+                        Err(e) => format!("Export failed: {}", e)
+                    };
+                }
+                text_typed |= text_field(ui, texture_input, "Texture", &mut texture_text);
+                if ui.button(None, "Load Texture") {
+                    texture_request = Some(texture_text.trim().to_owned());
+                }
+                if !file_status.is_empty() {
+                    ui.label(None, &file_status);
                 }
             });
 
-        if (last_width - cloth_width).abs() > 0.1 || (last_height - cloth_height).abs() > 0.1 {
-            cloth = Cloth::new(cloth_width as usize, cloth_height as usize, cloth_spacing, cloth_start_pos.x, cloth_start_pos.y);
-            selected_particle_idx = None;
-            last_width = cloth_width;
-            last_height = cloth_height;
-        }
-
-        let (mouse_x, mouse_y) = mouse_position();
-        let mouse_pos = vec2(mouse_x, mouse_y);
-
-        if is_mouse_button_pressed(MouseButton::Left) {
-            if !root_ui().is_mouse_over(mouse_pos) {
-                let mut closest_dist = f32::MAX;
-                let mut closest_idx = 0;
-                for (i, p) in cloth.particles.iter().enumerate() {
-                    let dist_sq = (p.pos - mouse_pos).length_squared();
-                    if dist_sq < closest_dist {
-                        closest_dist = dist_sq;
-                        closest_idx = i;
+        // Typing a size or a texture path shouldn't also drop pins or cut the cloth, so the
+        // shortcuts are off from the first key a text field takes until the next click, which is
+        // what moves its focus.
+        if text_typed {
+            typing = true;
+        } else if is_mouse_button_pressed(MouseButton::Left) {
+            typing = false;
+        }
+
+        // Recordings and replays both start from a fresh cloth built from the saved settings,
+        // so a replay sees exactly the scene the recording did.
+        let mut restart: Option<(SavedSettings, Vec2)> = None;
+        if toggle_input_recording {
+            if let Some(recording) = input_recording.take() {
+                file_status = match recording.finish() {
+                    Ok(frames) => format!("Recorded {} frames of input to {}", frames, REPLAY_PATH),
+                    Err(e) => format!("Input recording failed: {}", e)
+                };
+            } else {
+                match InputRecording::create(REPLAY_PATH, cloth_start_pos) {
+                    Ok(recording) => {
+                        input_recording = Some(recording);
+                        file_status = format!("Recording input to {}", REPLAY_PATH);
+                        let settings = SavedSettings::new(&scene[selected_cloth].settings, gravity_magnitude, gravity_angle, cut_radius);
+                        restart = Some((settings, cloth_start_pos));
                     }
+                    Err(e) => file_status = format!("Input recording failed: {}", e)
+                }
+            }
+        }
+        // Loading is async, so it waits until the panel is done. A failed load keeps the texture
+        // already in use, or the flat fabric color if there isn't one.
+        if let Some(path) = texture_request.take() {
+            match load_texture(&path).await {
+                Ok(texture) => {
+                    file_status = format!("Loaded texture {}", path);
+                    cloth_texture = Some(texture);
                 }
-                if closest_dist < 400.0 {
-                    selected_particle_idx = Some(closest_idx);
+                Err(e) => file_status = format!("Texture load failed: {}", e)
+            }
+        }
+        if let Some(path) = replay_request.take() {
+            match Replay::load(&path) {
+                Ok(loaded) => {
+                    file_status = format!("Replaying {}", path);
+                    restart = Some((loaded.settings.clone(), loaded.start));
+                    replay = Some(loaded);
+                }
+                Err(e) => file_status = format!("Replay failed: {}", e)
+            }
+        }
+        if let Some((settings, start)) = restart {
+            scene = vec![SceneCloth::new(settings.cloth_settings(start))];
+            selected_cloth = 0;
+            gravity_magnitude = settings.gravity;
+            gravity_angle = settings.gravity_angle;
+            cut_radius = settings.cut_radius;
+            unrecorded_options!() = default_options;
+            obstacles.clear();
+            rect_obstacles.clear();
+            rect_start = None;
+            selected_particle = None;
+            selected_obstacle_idx = None;
+            dragged_anchor = None;
+            flicked_particle = None;
+            brush_particles.clear();
+            motion_paths.clear();
+            snapshot = None;
+            view = View::new();
+            paused = false;
+            accumulator = 0.0;
+            input.restart();
+        }
+
+        // A replay stands in for the keyboard and mouse until it runs out.
+        match replay.as_mut().map(Replay::next_frame) {
+            Some(Some(frame)) => {
+                if let Some(settings) = frame.settings {
+                    settings.apply_to(&mut scene[selected_cloth].settings);
+                    gravity_magnitude = settings.gravity;
+                    gravity_angle = settings.gravity_angle;
+                    cut_radius = settings.cut_radius;
+                }
+                input.advance(frame.input);
+            }
+            finished => {
+                if finished.is_some() {
+                    replay = None;
+                    file_status = "Replay finished".to_owned();
+                }
+                let mut frame = InputFrame::capture();
+                if typing {
+                    frame.release_keys();
+                }
+                input.advance(frame);
+            }
+        }
+        if let Some(recording) = input_recording.as_mut() {
+            let settings = SavedSettings::new(&scene[selected_cloth].settings, gravity_magnitude, gravity_angle, cut_radius);
+            if let Err(e) = recording.record(&input.current, settings) {
+                file_status = format!("Input recording failed: {}", e);
+                input_recording = None;
+            }
+        }
+
+        if save_settings {
+            let settings = SavedSettings::new(&scene[selected_cloth].settings, gravity_magnitude, gravity_angle, cut_radius);
+            file_status = match settings.save(SETTINGS_PATH) {
+                Ok(()) => format!("Saved settings to {}", SETTINGS_PATH),
+                Err(e) => format!("Saving settings failed: {}", e)
+            };
+        }
+
+        if render_preset != last_render_preset {
+            render = RenderConfig::preset(RenderPreset::ALL[render_preset]);
+            last_render_preset = render_preset;
+        }
+
+        if add_cloth {
+            // Offset each new cloth so it doesn't spawn on top of the previous one.
+            let start = cloth_start_pos + vec2(80.0, 40.0) * scene.len() as f32;
+            scene.push(SceneCloth::new(ClothSettings::new(20., 15., cloth_spacing, start)));
+            selected_cloth = scene.len() - 1;
+        }
+        if remove_cloth && scene.len() > 1 {
+            scene.remove(selected_cloth);
+            selected_cloth = selected_cloth.min(scene.len() - 1);
+            // Everything holding a (cloth, particle) pair would now point into the wrong cloth.
+            selected_particle = None;
+            dragged_anchor = None;
+            flicked_particle = None;
+            edit_anchor = None;
+            brush_particles.clear();
+            motion_paths.clear();
+        }
+
+        for (i, entry) in scene.iter_mut().enumerate() {
+            let mut config = entry.settings.config();
+            if config != entry.config || (rebuild_cloth && i == selected_cloth) {
+                // Slide a cloth that grew wider back left so it stays on screen where it fits.
+                let extent = config.width.saturating_sub(1) as f32 * config.spacing_x;
+                entry.settings.start.x = entry.settings.start.x.min(screen_width() - extent).max(0.0);
+                config = entry.settings.config();
+                // A refused size is still taken as the built config, so it isn't retried every
+                // frame; the old cloth stays until the settings change again.
+                let built = Cloth::try_new(&config, (memory_cap_mb * 1024.0 * 1024.0) as usize);
+                entry.config = config;
+                match built {
+                    Ok(cloth) => entry.cloth = cloth,
+                    Err(e) => {
+                        file_status = format!("Cloth {} not rebuilt: {}", i + 1, e);
+                        continue;
+                    }
+                }
+                entry.age = 0.0;
+                if selected_particle.is_some_and(|(cloth_idx, _)| cloth_idx == i) {
+                    selected_particle = None;
+                    brush_particles.clear();
+                }
+                if edit_anchor.is_some_and(|(cloth_idx, _)| cloth_idx == i) {
+                    edit_anchor = None;
+                }
+                motion_paths.retain(|&(cloth_idx, ..)| cloth_idx != i);
+            }
+        }
+
+        let mouse_screen = input.current.mouse;
+        let over_ui = input.current.over_ui;
+
+        // While cutting, or with Shift held, the wheel resizes the cut brush instead of zooming.
+        let shift_down = input.key_down(KeyCode::LeftShift) || input.key_down(KeyCode::RightShift);
+        let sizing_cut = input.button_down(MouseButton::Right) || shift_down;
+        let wheel = input.current.wheel;
+        if wheel != 0.0 && !over_ui {
+            if sizing_cut {
+                cut_radius = (cut_radius + wheel.signum() * 2.0).clamp(10.0, 50.0);
+            } else {
+                view.zoom_at(mouse_screen, if wheel > 0.0 { 1.1 } else { 1.0 / 1.1 });
+            }
+        }
+        if input.button_down(MouseButton::Middle) {
+            view.pan(last_mouse_screen, mouse_screen);
+        }
+        last_mouse_screen = mouse_screen;
+
+        // Everything below works in world coordinates.
+        let mouse_pos = view.screen_to_world(mouse_screen);
+        particle_hashes.resize_with(scene.len(), || SpatialHash::new(20.0));
+        for (hash, entry) in particle_hashes.iter_mut().zip(&scene) {
+            hash.rebuild(entry.cloth.particles().iter().map(|p| p.pos));
+        }
+
+        if input.key_pressed(KeyCode::Space) {
+            paused = !paused;
+        }
+        if input.key_pressed(KeyCode::Period) {
+            step_requested = true;
+        }
+        if input.key_down(KeyCode::Left) {
+            gravity_angle = (gravity_angle - GRAVITY_TURN_RATE * input.current.dt).max(-180.0);
+        }
+        if input.key_down(KeyCode::Right) {
+            gravity_angle = (gravity_angle + GRAVITY_TURN_RATE * input.current.dt).min(180.0);
+        }
+        if input.key_pressed(KeyCode::F3) {
+            show_stats = !show_stats;
+        }
+        if input.key_pressed(KeyCode::H) {
+            show_help = !show_help;
+        }
+        if input.key_pressed(KeyCode::R) {
+            scene[selected_cloth].cloth.reset_positions();
+            scene[selected_cloth].age = 0.0;
+            selected_particle = None;
+            flicked_particle = None;
+            dragged_anchor = None;
+            brush_particles.clear();
+        }
+        if input.key_pressed(KeyCode::F5) {
+            snapshot = Some(scene.iter().map(|e| e.cloth.snapshot()).collect());
+            file_status = "Snapshot taken".to_owned();
+        }
+        if input.key_pressed(KeyCode::F9) {
+            if let Some(states) = &snapshot {
+                // Cloths added since the snapshot are left as they are.
+                for (entry, state) in scene.iter_mut().zip(states) {
+                    entry.cloth.restore(state);
+                }
+                selected_particle = None;
+                flicked_particle = None;
+                dragged_anchor = None;
+                brush_particles.clear();
+                // Restoring can drop particles, so paths on any restored cloth may point past the end.
+                motion_paths.retain(|&(cloth_idx, ..)| cloth_idx >= states.len());
+                file_status = "Snapshot restored".to_owned();
+            }
+        }
+        if input.key_pressed(KeyCode::F11) {
+            fullscreen = !fullscreen;
+            set_fullscreen(fullscreen);
+        }
+
+        let ctrl_down = input.key_down(KeyCode::LeftControl) || input.key_down(KeyCode::RightControl);
+        if ctrl_down && input.key_pressed(KeyCode::Z) {
+            let restored = scene[selected_cloth].cloth.undo_removal();
+            cut_feedback = Some((restored, "restored", mouse_screen, get_time()));
+        }
+
+        if input.key_pressed(KeyCode::C) && !over_ui {
+            obstacles.push(CircleObstacle { center: mouse_pos, radius: obstacle_radius });
+        }
+
+        // Edit mode builds shapes in the selected cloth by hand and replaces the usual mouse tools.
+        // Left clicks add a particle in empty space, or join two particles clicked in turn with
+        // a spring; right clicks delete the particle or spring under the cursor.
+        if !edit_mode || edit_anchor.is_some_and(|(cloth_idx, _)| cloth_idx != selected_cloth) {
+            edit_anchor = None;
+        }
+        if edit_mode && !over_ui {
+            let cloth = &mut scene[selected_cloth].cloth;
+            let near = particle_hashes[selected_cloth].query_nearest(mouse_pos)
+                .filter(|&i| cloth.particles().get(i).is_some_and(|p| !p.absent && p.pos.distance(mouse_pos) < 20.0));
+            if input.button_pressed(MouseButton::Left) {
+                edit_anchor = match (edit_anchor, near) {
+                    (None, None) => {
+                        cloth.add_particle(Particle::new(mouse_pos.x, mouse_pos.y));
+                        None
+                    }
+                    (None, Some(idx)) => Some((selected_cloth, idx)),
+                    (Some((_, first)), Some(idx)) if first != idx => {
+                        cloth.add_spring(first, idx, SpringKind::Structural);
+                        None
+                    }
+                    // Clicking the first particle again or empty space lets it go.
+                    _ => None
+                };
+            }
+            if input.button_pressed(MouseButton::Right) {
+                if let Some(idx) = near {
+                    // Every later particle moves down an index, so anything holding one is dropped.
+                    cloth.remove_particle(idx);
+                    selected_particle = None;
+                    dragged_anchor = None;
+                    flicked_particle = None;
+                    brush_particles.clear();
+                    motion_paths.retain(|&(cloth_idx, ..)| cloth_idx != selected_cloth);
+                } else if let Some(idx) = nearest_spring(cloth, mouse_pos, 10.0) {
+                    cloth.remove_spring(idx);
+                }
+                edit_anchor = None;
+            }
+        }
+
+        let placing_rect = input.key_down(KeyCode::B);
+        if input.button_pressed(MouseButton::Left) && !over_ui && !edit_mode && placing_rect {
+            rect_start = Some(mouse_pos);
+        }
+
+        if input.button_pressed(MouseButton::Left) && !over_ui && !edit_mode && !placing_rect {
+            selected_obstacle_idx = obstacles.iter().position(|o| o.center.distance(mouse_pos) < o.radius);
+        }
+
+        if input.button_pressed(MouseButton::Left) && !over_ui && !edit_mode && !placing_rect && selected_obstacle_idx.is_none() {
+            let nearest = nearest_particle(&scene, &particle_hashes, mouse_pos, 20.0);
+            if input.key_down(KeyCode::A) {
+                dragged_anchor = nearest_pinned(&scene, mouse_pos, 30.0);
+            } else if input.key_down(KeyCode::F) {
+                flicked_particle = nearest;
+            } else if input.key_down(KeyCode::G) {
+                // Attaches a path to the nearest pinned particle, or takes it off again.
+                if let Some((cloth_idx, idx)) = nearest_pinned(&scene, mouse_pos, 30.0) {
+                    if let Some(i) = motion_paths.iter().position(|&(c, p, ..)| (c, p) == (cloth_idx, idx)) {
+                        motion_paths.remove(i);
+                    } else {
+                        let path = MotionPath {
+                            shape: PathShape::ALL[path_shape],
+                            origin: scene[cloth_idx].cloth.particles()[idx].pos,
+                            amplitude: path_amplitude,
+                            frequency: path_frequency
+                        };
+                        motion_paths.push((cloth_idx, idx, path, input.time));
+                    }
+                }
+            } else if input.key_down(KeyCode::E) {
+                for entry in scene.iter_mut() {
+                    entry.cloth.apply_radial_impulse(mouse_pos, burst_radius, burst_strength);
+                }
+            } else if input.key_down(KeyCode::T) || input.key_down(KeyCode::S) {
+                // Heat and reinforcement are painted while the button is held, below.
+            } else if input.key_down(KeyCode::P) {
+                if let Some((cloth_idx, idx)) = nearest {
+                    scene[cloth_idx].cloth.particles_mut()[idx].toggle_pin();
+                }
+            } else if input.key_down(KeyCode::M) {
+                if let Some((cloth_idx, idx)) = nearest {
+                    let step = if shift_down { -MASS_STEP } else { MASS_STEP };
+                    let p = &mut scene[cloth_idx].cloth.particles_mut()[idx];
+                    p.set_mass(p.mass + step);
+                }
+            } else {
+                // With dragging pinned particles off, anchors can't be grabbed or swept up by
+                // the brush, so reaching for the cloth next to one grabs the cloth instead.
+                selected_particle = if drag_pinned { nearest } else { nearest_where(&scene, mouse_pos, 20.0, |p| !p.is_pinned) };
+                // The grabbed particle follows the cursor exactly; the rest of the brush moves
+                // with the cursor by a weight that falls off smoothly towards its edge. The brush
+                // only reaches into the cloth that owns the grabbed particle.
+                if let Some((cloth_idx, idx)) = selected_particle.filter(|_| grab_radius > 0.0) {
+                    let particles = scene[cloth_idx].cloth.particles();
+                    brush_particles = particle_hashes[cloth_idx].query_radius(mouse_pos, grab_radius).into_iter()
+                        .filter(|&i| i != idx && i < particles.len() && !particles[i].absent && (drag_pinned || !particles[i].is_pinned))
+                        .map(|i| {
+                            let t = (particles[i].pos.distance(mouse_pos) / grab_radius).min(1.0);
+                            (i, (1.0 - t * t).powi(2))
+                        })
+                        .collect();
                 }
             }
         }
         
-        if is_mouse_button_down(MouseButton::Left) {
-            if let Some(idx) = selected_particle_idx {
-                if idx < cloth.particles.len() {
-                    let original_pos = cloth.particles[idx].pos;
-                    cloth.particles[idx].pos = mouse_pos;
-                    cloth.particles[idx].old_pos = original_pos;
+        for entry in scene.iter_mut() {
+            entry.grab = None;
+            entry.holds = false;
+        }
+        let mut held_particle = None;
+        if input.button_down(MouseButton::Left) {
+            if input.key_down(KeyCode::T) && !over_ui && !paused {
+                for entry in scene.iter_mut() {
+                    entry.cloth.apply_heat(mouse_pos, heat_radius, heat_rate * input.current.dt);
+                }
+            }
+            // Uses the cut radius, so reinforcing a region and cutting through it reach the
+            // same springs.
+            if input.key_down(KeyCode::S) && !over_ui && !edit_mode {
+                for entry in scene.iter_mut() {
+                    entry.cloth.reinforce(mouse_pos, cut_radius, reinforce_rate * input.current.dt);
+                }
+            }
+            if let Some(idx) = selected_obstacle_idx {
+                obstacles[idx].center = mouse_pos;
+            }
+            // Move the anchor itself rather than pulling on it, so it stays pinned wherever it
+            // is dropped.
+            if let Some(p) = dragged_anchor.and_then(|(cloth_idx, idx)| scene.get_mut(cloth_idx)?.cloth.particles_mut().get_mut(idx)) {
+                p.pos = mouse_pos;
+                p.old_pos = mouse_pos;
+            }
+            // A soft grab leaves the moving to the simulation, which pulls the particle along
+            // on a spring. The brush only follows hard grabs.
+            if let Some((cloth_idx, idx)) = selected_particle.filter(|_| soft_grab) {
+                if idx < scene[cloth_idx].cloth.particles().len() {
+                    scene[cloth_idx].grab = Some((idx, mouse_pos));
                 } else {
-                    selected_particle_idx = None;
+                    selected_particle = None;
+                }
+            } else if let Some((cloth_idx, idx)) = selected_particle.filter(|_| limit_drag) {
+                // With the drag limited, the simulation moves the particle and its brush towards
+                // the cursor by at most the limit every substep, so a fast flick can't carry them
+                // clean over an obstacle between two collision checks.
+                if idx < scene[cloth_idx].cloth.particles().len() {
+                    scene[cloth_idx].holds = true;
+                    held_particle = Some(idx);
+                } else {
+                    selected_particle = None;
+                }
+            } else if let Some((cloth_idx, idx)) = selected_particle {
+                let particles = scene[cloth_idx].cloth.particles_mut();
+                let mouse_delta = mouse_pos - last_mouse_pos;
+                if let Some(p) = particles.get_mut(idx) {
+                    p.old_pos = p.pos;
+                    p.pos = mouse_pos;
+                } else {
+                    selected_particle = None;
+                }
+
+                for &(idx, weight) in &brush_particles {
+                    if let Some(p) = particles.get_mut(idx) {
+                        p.old_pos = p.pos;
+                        p.pos += mouse_delta * weight;
+                    }
                 }
             }
         }
 
-        if is_mouse_button_released(MouseButton::Left) {
-            selected_particle_idx = None;
+        if input.button_released(MouseButton::Left) {
+            // The drag from the particle to the cursor becomes a velocity along it.
+            if let Some(p) = flicked_particle.and_then(|(cloth_idx, idx)| scene.get_mut(cloth_idx)?.cloth.particles_mut().get_mut(idx)) {
+                if !p.is_pinned {
+                    p.old_pos = p.pos - (mouse_pos - p.pos) * flick_strength;
+                }
+            }
+            if let Some(start) = rect_start.take() {
+                let rect = RectObstacle::from_corners(start, mouse_pos);
+                if (rect.max - rect.min).min_element() > 1.0 {
+                    rect_obstacles.push(rect);
+                }
+            }
+            flicked_particle = None;
+            dragged_anchor = None;
+            selected_particle = None;
+            selected_obstacle_idx = None;
+            brush_particles.clear();
         }
         
-        if is_mouse_button_down(MouseButton::Right) {
-            let mouse_pos = vec2(mouse_position().0, mouse_position().1);
-
-            cloth.springs.retain(|spring| {
-                let p1 = cloth.particles[spring.p1_idx];
-                let p2 = cloth.particles[spring.p2_idx];
-                let dist_to_spring = distance_point_to_segment(mouse_pos, p1.pos, p2.pos);
-                dist_to_spring > cut_radius
-            });
+        if input.button_down(MouseButton::Right) && !edit_mode {
+            let mut severed = 0;
+            for entry in scene.iter_mut() {
+                severed += if hard_cut {
+                    entry.cloth.hard_cut(last_mouse_pos, mouse_pos, cut_radius)
+                } else {
+                    entry.cloth.cut(mouse_pos, cut_radius)
+                };
+            }
+            // Counts add up over a stroke and restart with each new click.
+            let stroke_total = match cut_feedback {
+                Some((count, "cut", _, _)) if !input.button_pressed(MouseButton::Right) => count + severed,
+                _ => severed
+            };
+            cut_feedback = Some((stroke_total, "cut", mouse_screen, get_time()));
+        }
+        last_mouse_pos = mouse_pos;
+
+        // Driven particles stay pinned, so the solver treats them as immovable wherever the path
+        // puts them. Unpinning one or loading over it drops its path.
+        motion_paths.retain(|&(cloth_idx, idx, path, started)| {
+            let Some(p) = scene.get_mut(cloth_idx).and_then(|e| e.cloth.particles_mut().get_mut(idx)) else { return false };
+            if !p.is_pinned {
+                return false;
+            }
+            p.pos = path.position_at((input.time - started) as f32);
+            p.old_pos = p.pos;
+            true
+        });
+
+        let wind = Wind::new(Vec2::from_angle(f32::to_radians(wind_angle)) * wind_strength, gust_amplitude, gust_frequency);
+
+        // The flip takes effect in the settings straight away, so it's what gets saved, while the
+        // gravity the cloth feels eases over from where it was. The sliders still steer the
+        // target mid-flip, and flipping again mid-flip eases on from the gravity felt right now.
+        let mut gravity = gravity_vector(gravity_magnitude, gravity_angle);
+        if let Some((from, started)) = gravity_flip {
+            let t = (input.time - started) / GRAVITY_FLIP_SECONDS;
+            if t < 1.0 {
+                let eased = t * t * (3.0 - 2.0 * t);
+                gravity = from.lerp(gravity, eased as f32);
+            } else {
+                gravity_flip = None;
+            }
+        }
+        if flip_gravity {
+            gravity_flip = Some((gravity, input.time));
+            gravity_angle = if gravity_angle > 0.0 { gravity_angle - 180.0 } else { gravity_angle + 180.0 };
+        }
+
+        // Scene-wide settings; each cloth fills in its own solver and tearing values.
+        let params = SimParams {
+            gravity,
+            wind: wind.force_at(input.time as f32),
+            floor_y: floor_enabled.then_some(floor_y),
+            // The window's rectangle at zoom 1, so it follows resizing but not the camera.
+            walls: walls_enabled.then(|| RectObstacle { min: Vec2::ZERO, max: vec2(screen_width(), screen_height()) }),
+            collision,
+            aerodynamic_wind,
+            heat_weakening,
+            heat_diffusion,
+            heat_decay,
+            attractor: (attractor_enabled && !over_ui).then_some((mouse_pos, attractor_strength)),
+            cloth_collision: cloth_collision.then_some(cloth_collision_distance),
+            grab_stiffness,
+            held: held_particle.map(|idx| HeldParticle { idx, target: mouse_pos, max_step: max_drag_step, brush: &brush_particles }),
+            obstacles: &obstacles,
+            rect_obstacles: &rect_obstacles,
+            ..SimParams::default()
+        };
+
+        if settle_cloth {
+            let entry = &mut scene[selected_cloth];
+            let steps = entry.cloth.settle(&entry.sim_params(&params), SETTLE_MAX_STEPS, SETTLE_TOLERANCE);
+            entry.age += steps as f32 * FIXED_DT;
+            file_status = if steps < SETTLE_MAX_STEPS {
+                format!("Settled in {} steps", steps)
+            } else {
+                format!("Still moving after {} steps", steps)
+            };
+        }
+
+        let update_start = Instant::now();
+        let mut frame_iterations: Option<usize> = None;
+        if recorder.active && !paused {
+            // Advance a fixed amount of simulated time per captured frame, however long the
+            // capture itself takes.
+            for _ in 0..(RECORD_FRAME_DT / FIXED_DT).round() as usize {
+                frame_iterations = frame_iterations.max(Some(step_scene(&mut scene, &mut cloth_collider, FIXED_DT, &params)));
+            }
+        } else if !paused {
+            accumulator += input.current.dt;
+            let mut steps = 0;
+            while accumulator >= FIXED_DT && steps < MAX_STEPS_PER_FRAME {
+                frame_iterations = frame_iterations.max(Some(step_scene(&mut scene, &mut cloth_collider, FIXED_DT, &params)));
+                accumulator -= FIXED_DT;
+                steps += 1;
+            }
+            // After a stall, drop the backlog instead of trying to catch up over the next frames.
+            if steps == MAX_STEPS_PER_FRAME {
+                accumulator = 0.0;
+            }
+        } else if step_requested {
+            frame_iterations = frame_iterations.max(Some(step_scene(&mut scene, &mut cloth_collider, FIXED_DT, &params)));
+        }
+        let update_time = update_start.elapsed();
+        // Keep showing the last count while paused.
+        if let Some(iterations) = frame_iterations {
+            iterations_used = iterations;
         }
 
-        cloth.update(
-            dt,
-            iterations as usize, 
-            vec2(0.0, gravity_y),
-            stiffness,
-            tear_threshold,
-        );
-        cloth.draw();
+        for entry in scene.iter_mut() {
+            entry.cloth.end_frame();
+        }
 
-        draw_text("Left Mouse: Drag and Tear | Right Mouse: Cut", 10.0, 20.0, 20.0, WHITE);
+        let draw_start = Instant::now();
+        if show_wind {
+            draw_wind_field(params.wind);
+        }
+        set_camera(&view.camera());
+        if floor_enabled {
+            let left = view.screen_to_world(Vec2::ZERO).x;
+            let right = view.screen_to_world(vec2(screen_width(), 0.0)).x;
+            draw_line(left, floor_y, right, floor_y, 2.0 / view.zoom, GRAY);
+        }
+        if let Some(walls) = &params.walls {
+            let size = walls.max - walls.min;
+            draw_rectangle_lines(walls.min.x, walls.min.y, size.x, size.y, 2.0 / view.zoom, GRAY);
+        }
+        for obstacle in &obstacles {
+            draw_circle_lines(obstacle.center.x, obstacle.center.y, obstacle.radius, 2.0, GRAY);
+        }
+        for obstacle in &rect_obstacles {
+            let size = obstacle.max - obstacle.min;
+            draw_rectangle_lines(obstacle.min.x, obstacle.min.y, size.x, size.y, 2.0, GRAY);
+        }
+        if let Some(start) = rect_start {
+            let preview = RectObstacle::from_corners(start, mouse_pos);
+            let size = preview.max - preview.min;
+            draw_rectangle_lines(preview.min.x, preview.min.y, size.x, size.y, 1.0 / view.zoom, LIGHTGRAY);
+        }
+        let light = {
+            let (angle, elevation) = (light_angle.to_radians(), light_elevation.to_radians());
+            vec3(angle.cos() * elevation.cos(), angle.sin() * elevation.cos(), elevation.sin())
+        };
+        for entry in &scene {
+            match DrawMode::ALL[draw_mode] {
+                DrawMode::Wireframe => entry.cloth.draw(None, 1.0, &render),
+                DrawMode::Stress => {
+                    let tear_strain = entry.settings.tear_strain(entry.cloth.spacing());
+                    entry.cloth.draw(Some(tear_strain), entry.settings.rest_length_scale, &render)
+                }
+                DrawMode::Speed => entry.cloth.draw_speed(max_draw_speed, &render),
+                DrawMode::Components => entry.cloth.draw_components(&render),
+                DrawMode::Mesh => entry.cloth.draw_filled(cloth_texture.as_ref(), &render, light),
+                DrawMode::Triangles => entry.cloth.draw_triangles(&render),
+                DrawMode::Shaded => match &cloth_material {
+                    Some(material) => entry.cloth.draw_shaded(material, cloth_texture.as_ref(), &render, light),
+                    None => entry.cloth.draw(None, 1.0, &render)
+                }
+            }
+        }
+        // The spring a cut or an edit would reach first, across every cloth.
+        let hovered_spring = scene.iter()
+            .filter(|_| !over_ui)
+            .filter_map(|entry| {
+                let spring = entry.cloth.springs()[nearest_spring(&entry.cloth, mouse_pos, cut_radius)?];
+                let (a, b) = (entry.cloth.particles()[spring.p1_idx].pos, entry.cloth.particles()[spring.p2_idx].pos);
+                Some((a, b, distance_point_to_segment(mouse_pos, a, b)))
+            })
+            .min_by(|x, y| x.2.total_cmp(&y.2));
+        if let Some((a, b, _)) = hovered_spring {
+            draw_line(a.x, a.y, b.x, b.y, (render.line_thickness * 3.0).max(3.0 / view.zoom), YELLOW);
+        }
+        if show_bounds {
+            for (min, max) in scene.iter().filter_map(|e| e.cloth.bounds()) {
+                draw_rectangle_lines(min.x, min.y, max.x - min.x, max.y - min.y, 1.0 / view.zoom, GREEN);
+            }
+        }
+        if let Some(p) = edit_anchor.and_then(|(cloth_idx, idx)| scene.get(cloth_idx)?.cloth.particles().get(idx)) {
+            draw_circle_lines(p.pos.x, p.pos.y, 8.0 / view.zoom, 2.0 / view.zoom, YELLOW);
+            draw_line(p.pos.x, p.pos.y, mouse_pos.x, mouse_pos.y, 1.0 / view.zoom, YELLOW);
+        }
+        if sizing_cut && !over_ui {
+            draw_circle_lines(mouse_pos.x, mouse_pos.y, cut_radius, 1.0 / view.zoom, LIGHTGRAY);
+        }
+        if let Some(p) = flicked_particle.and_then(|(cloth_idx, idx)| scene.get(cloth_idx)?.cloth.particles().get(idx)) {
+            draw_arrow(p.pos, mouse_pos, 2.0 / view.zoom, ORANGE);
+        }
+        set_default_camera();
+        let draw_time = draw_start.elapsed();
+
+        if !over_ui {
+            if let Some((cloth_idx, idx)) = nearest_particle(&scene, &particle_hashes, mouse_pos, 20.0) {
+                let cloth = &scene[cloth_idx].cloth;
+                let p = cloth.particles()[idx];
+                // Particles split off by a hard cut or added in edit mode sit past the end of the grid.
+                let grid = if idx < cloth.width() * cloth.height() {
+                    format!("({}, {})", idx % cloth.width(), idx / cloth.width())
+                } else {
+                    "off grid".to_owned()
+                };
+                let lines = [
+                    format!("Cloth {} particle {} at {}", cloth_idx + 1, idx, grid),
+                    format!("pos ({:.1}, {:.1}){}", p.pos.x, p.pos.y, if p.is_pinned { " pinned" } else { "" }),
+                ];
+                for (i, line) in lines.iter().enumerate() {
+                    draw_text(line, mouse_screen.x + 14.0, mouse_screen.y + 18.0 * (i as f32 + 1.0), 16.0, LIGHTGRAY);
+                }
+            }
+        }
+
+        // Labels go on after the camera is reset so the text stays readable at any zoom.
+        if show_bounds {
+            for entry in &scene {
+                let Some((min, max)) = entry.cloth.bounds() else { continue };
+                let size = max - min;
+                let text = format!("{:.0} x {:.0}, area {:.0}", size.x, size.y, entry.cloth.area());
+                let corner = view.world_to_screen(min);
+                draw_text(&text, corner.x, corner.y - 6.0, 16.0, GREEN);
+            }
+        }
+
+        if let Some((count, action, pos, time)) = cut_feedback {
+            let age = get_time() - time;
+            if age < CUT_FEEDBACK_SECONDS {
+                let alpha = 1.0 - (age / CUT_FEEDBACK_SECONDS) as f32;
+                let text = format!("{} spring{} {}", count, if count == 1 { "" } else { "s" }, action);
+                draw_text(&text, pos.x + 14.0, pos.y - 10.0, 18.0, Color::new(1.0, 0.85, 0.3, alpha));
+            } else {
+                cut_feedback = None;
+            }
+        }
+
+        if show_stats {
+            let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+            let energy = energy_stats(scene.iter().map(|e| &e.cloth), params.gravity, vec2(0.0, floor_y));
+            draw_overlay(&[
+                format!("FPS: {}", get_fps()),
+                format!("Frame time: {:.2} ms", get_frame_time() * 1000.0),
+                format!("Cloths: {}", scene.len()),
+                format!("Particles: {}", scene.iter().map(|e| e.cloth.particles().len()).sum::<usize>()),
+                format!("Springs: {}", scene.iter().map(|e| e.cloth.springs().len()).sum::<usize>()),
+                format!("Pieces: {}", scene.iter().map(|e| e.cloth.connected_components()).sum::<usize>()),
+                format!("Memory: {:.0} KB", scene.iter().map(|e| e.cloth.memory_footprint()).sum::<usize>() as f64 / 1024.0),
+                format!("Update: {:.2} ms", ms(update_time)),
+                format!("Iterations: {}", iterations_used),
+                format!("Draw: {:.2} ms", ms(draw_time)),
+                format!("Kinetic energy: {:.0}", energy.kinetic),
+                format!("Potential energy: {:.0}", energy.potential),
+                format!("Total energy: {:.0}", energy.kinetic + energy.potential),
+                format!("Center of mass: ({:.1}, {:.1})", energy.center_of_mass.x, energy.center_of_mass.y),
+            ]);
+        }
+
+        let hint: Vec<String> = BINDINGS[..2].iter().map(|(keys, action)| format!("{}: {}", keys, action)).collect();
+        draw_text(&format!("{} | H: All controls", hint.join(" | ")), 10.0, 20.0, 20.0, WHITE);
+        if show_help {
+            draw_help();
+        }
+        if paused {
+            draw_text("PAUSED", screen_width() - 90.0, 20.0, 20.0, YELLOW);
+        }
+
+        if recorder.active {
+            recorder.capture();
+            draw_circle(screen_width() - 110.0, 14.0, 6.0, RED);
+        }
 
         next_frame().await;
     }
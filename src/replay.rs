@@ -0,0 +1,353 @@
+// Recording and playback of the keyboard and mouse, so a session can be replayed exactly.
+//
+// A replay file is plain text. The first line is `cloth-replay <version>`, the second is
+// `start <x> <y>`, the spot the fresh cloth is built at, and every following line is one
+// event, `<frame> <event> <values>`:
+//
+//   frame <dt>           starts a frame that advanced the clock by `dt` seconds
+//   mouse <x> <y>        the cursor moved to this screen position
+//   down <name>          a key or mouse button was pressed
+//   up <name>            it was released
+//   wheel <delta>        the mouse wheel turned
+//   ui <0 or 1>          the cursor moved onto or off the settings panel
+//   settings <json>      the saved settings changed, e.g. from a slider
+//
+// Only changes are written, apart from the `frame` line that starts every frame.
+//
+// Panel controls outside the saved settings are not recorded. Instead, a recording and its
+// replay both start with every one of them at its startup value, and the panel hides them for
+// as long as the recording or replay runs, so they can't change in between. Panel buttons
+// such as Add Cloth, Settle and Flip Gravity are hidden too. Keyboard shortcuts are recorded
+// like any other key.
+
+use macroquad::prelude::*;
+use macroquad::ui::root_ui;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use crate::SavedSettings;
+
+const REPLAY_VERSION: u32 = 1;
+
+// Every key the sim responds to. A recording stores keys by these names.
+const INPUT_KEYS: [(KeyCode, &str); 25] = [
+    (KeyCode::Space, "Space"),
+    (KeyCode::Period, "Period"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::LeftShift, "LeftShift"),
+    (KeyCode::RightShift, "RightShift"),
+    (KeyCode::LeftControl, "LeftControl"),
+    (KeyCode::RightControl, "RightControl"),
+    (KeyCode::A, "A"),
+    (KeyCode::B, "B"),
+    (KeyCode::C, "C"),
+    (KeyCode::E, "E"),
+    (KeyCode::F, "F"),
+    (KeyCode::G, "G"),
+    (KeyCode::H, "H"),
+    (KeyCode::M, "M"),
+    (KeyCode::P, "P"),
+    (KeyCode::R, "R"),
+    (KeyCode::S, "S"),
+    (KeyCode::T, "T"),
+    (KeyCode::Z, "Z"),
+    (KeyCode::F3, "F3"),
+    (KeyCode::F5, "F5"),
+    (KeyCode::F9, "F9"),
+    (KeyCode::F11, "F11")
+];
+
+const INPUT_BUTTONS: [(MouseButton, &str); 3] = [
+    (MouseButton::Left, "LeftMouse"),
+    (MouseButton::Right, "RightMouse"),
+    (MouseButton::Middle, "MiddleMouse")
+];
+
+// The state of the keyboard and mouse during one frame. Keys and buttons are bitmasks over
+// `INPUT_KEYS` and `INPUT_BUTTONS`.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct InputFrame {
+    pub dt: f32,
+    pub mouse: Vec2,
+    pub wheel: f32,
+    pub over_ui: bool,
+    keys: u32,
+    buttons: u32
+}
+
+impl InputFrame {
+    // Reads the live keyboard and mouse. Call it after the settings panel is drawn, so
+    // `over_ui` sees this frame's panel.
+    pub fn capture() -> Self {
+        let mut frame = InputFrame {
+            dt: get_frame_time(),
+            mouse: Vec2::from(mouse_position()),
+            wheel: mouse_wheel().1,
+            over_ui: false,
+            keys: 0,
+            buttons: 0
+        };
+        for (i, &(key, _)) in INPUT_KEYS.iter().enumerate() {
+            if is_key_down(key) {
+                frame.keys |= 1 << i;
+            }
+        }
+        for (i, &(button, _)) in INPUT_BUTTONS.iter().enumerate() {
+            if is_mouse_button_down(button) {
+                frame.buttons |= 1 << i;
+            }
+        }
+        frame.apply_touches(&touches());
+        frame.over_ui = root_ui().is_mouse_over(frame.mouse);
+        frame
+    }
+
+    // Touches already raise mouse events, so one finger drags like the left button and works
+    // the settings panel. A second finger turns the first one into the cut tool instead, like
+    // the right button, and the cursor stays on the first finger.
+    fn apply_touches(&mut self, touches: &[Touch]) {
+        let held: Vec<&Touch> = touches.iter()
+            .filter(|t| !matches!(t.phase, TouchPhase::Ended | TouchPhase::Cancelled))
+            .collect();
+        let Some(first) = held.iter().min_by_key(|t| t.id).filter(|_| held.len() > 1) else { return };
+        self.mouse = first.position;
+        self.buttons = (self.buttons & !button_bit(MouseButton::Left)) | button_bit(MouseButton::Right);
+    }
+
+    // Lets go of every key, for a frame where the keyboard is typing into the panel.
+    pub fn release_keys(&mut self) {
+        self.keys = 0;
+    }
+
+    // Presses or releases the key or button called `name`. Returns false for unknown names.
+    fn set_named(&mut self, name: &str, down: bool) -> bool {
+        let (mask, bit) = if let Some(i) = INPUT_KEYS.iter().position(|&(_, n)| n == name) {
+            (&mut self.keys, i)
+        } else if let Some(i) = INPUT_BUTTONS.iter().position(|&(_, n)| n == name) {
+            (&mut self.buttons, i)
+        } else {
+            return false;
+        };
+        if down {
+            *mask |= 1 << bit;
+        } else {
+            *mask &= !(1 << bit);
+        }
+        true
+    }
+}
+
+fn key_bit(key: KeyCode) -> u32 {
+    let i = INPUT_KEYS.iter().position(|&(k, _)| k == key).expect("key missing from INPUT_KEYS");
+    1 << i
+}
+
+fn button_bit(button: MouseButton) -> u32 {
+    let i = INPUT_BUTTONS.iter().position(|&(b, _)| b == button).expect("button missing from INPUT_BUTTONS");
+    1 << i
+}
+
+// This frame's input and the last one's. Presses and releases are the changes between the
+// two, so live and replayed input see exactly the same edges.
+#[derive(Default)]
+pub struct Input {
+    pub current: InputFrame,
+    previous: InputFrame,
+    // Seconds of input since the sim started or the recording or replay began.
+    pub time: f64
+}
+
+impl Input {
+    pub fn advance(&mut self, frame: InputFrame) {
+        self.previous = self.current;
+        self.current = frame;
+        self.time += frame.dt as f64;
+    }
+
+    // Starts the clock over, with nothing held, for a recording or replay.
+    pub fn restart(&mut self) {
+        *self = Input::default();
+    }
+
+    pub fn key_down(&self, key: KeyCode) -> bool {
+        self.current.keys & key_bit(key) != 0
+    }
+
+    pub fn key_pressed(&self, key: KeyCode) -> bool {
+        self.key_down(key) && self.previous.keys & key_bit(key) == 0
+    }
+
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        self.current.buttons & button_bit(button) != 0
+    }
+
+    pub fn button_pressed(&self, button: MouseButton) -> bool {
+        self.button_down(button) && self.previous.buttons & button_bit(button) == 0
+    }
+
+    pub fn button_released(&self, button: MouseButton) -> bool {
+        !self.button_down(button) && self.previous.buttons & button_bit(button) != 0
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Version(String),
+    BadLine(usize)
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Version(header) => write!(f, "expected 'cloth-replay {}', found '{}'", REPLAY_VERSION, header),
+            ReplayError::BadLine(line) => write!(f, "can't read line {}", line)
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+pub struct InputRecording {
+    writer: BufWriter<File>,
+    frame: usize,
+    last: InputFrame,
+    last_settings: Option<SavedSettings>
+}
+
+impl InputRecording {
+    pub fn create(path: &str, start: Vec2) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "cloth-replay {}", REPLAY_VERSION)?;
+        writeln!(writer, "start {} {}", start.x, start.y)?;
+        Ok(InputRecording { writer, frame: 0, last: InputFrame::default(), last_settings: None })
+    }
+
+    // Writes whatever changed since the last frame. The first frame always writes the settings.
+    pub fn record(&mut self, input: &InputFrame, settings: SavedSettings) -> std::io::Result<()> {
+        let frame = self.frame;
+        writeln!(self.writer, "{} frame {}", frame, input.dt)?;
+        if self.last_settings.as_ref() != Some(&settings) {
+            let json = serde_json::to_string(&settings).map_err(std::io::Error::other)?;
+            writeln!(self.writer, "{} settings {}", frame, json)?;
+            self.last_settings = Some(settings);
+        }
+        if input.mouse != self.last.mouse {
+            writeln!(self.writer, "{} mouse {} {}", frame, input.mouse.x, input.mouse.y)?;
+        }
+        if input.over_ui != self.last.over_ui {
+            writeln!(self.writer, "{} ui {}", frame, input.over_ui as u8)?;
+        }
+        if input.wheel != 0.0 {
+            writeln!(self.writer, "{} wheel {}", frame, input.wheel)?;
+        }
+        let last = self.last;
+        for (i, &(_, name)) in INPUT_KEYS.iter().enumerate() {
+            self.record_change(name, input.keys & (1 << i), last.keys & (1 << i))?;
+        }
+        for (i, &(_, name)) in INPUT_BUTTONS.iter().enumerate() {
+            self.record_change(name, input.buttons & (1 << i), last.buttons & (1 << i))?;
+        }
+        self.last = *input;
+        self.frame += 1;
+        Ok(())
+    }
+
+    fn record_change(&mut self, name: &str, now: u32, before: u32) -> std::io::Result<()> {
+        if (now != 0) == (before != 0) {
+            return Ok(());
+        }
+        writeln!(self.writer, "{} {} {}", self.frame, if now != 0 { "down" } else { "up" }, name)
+    }
+
+    // Returns how many frames were written.
+    pub fn finish(mut self) -> std::io::Result<usize> {
+        self.writer.flush()?;
+        Ok(self.frame)
+    }
+}
+
+// One recorded frame: its input, and the settings to apply first if they changed.
+pub struct ReplayFrame {
+    pub input: InputFrame,
+    pub settings: Option<SavedSettings>
+}
+
+pub struct Replay {
+    pub start: Vec2,
+    // The settings the recording started with, to build the fresh cloth from.
+    pub settings: SavedSettings,
+    frames: std::vec::IntoIter<ReplayFrame>
+}
+
+impl Replay {
+    // Reads and checks the whole file up front, so a bad recording fails before it starts.
+    pub fn load(path: &str) -> Result<Self, ReplayError> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header != format!("cloth-replay {}", REPLAY_VERSION) {
+            return Err(ReplayError::Version(header));
+        }
+        let start_line = lines.next().transpose()?.unwrap_or_default();
+        let start = match start_line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["start", x, y] => x.parse().ok().zip(y.parse().ok()).map(|(x, y)| vec2(x, y)),
+            _ => None
+        }.ok_or(ReplayError::BadLine(2))?;
+
+        let mut frames: Vec<ReplayFrame> = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let line_number = i + 3;
+            let bad = || ReplayError::BadLine(line_number);
+            let mut parts = line.splitn(3, ' ');
+            let (Some(frame), Some(event)) = (parts.next(), parts.next()) else { return Err(bad()) };
+            let values = parts.next().unwrap_or("");
+            let frame: usize = frame.parse().map_err(|_| bad())?;
+
+            if event == "frame" {
+                if frame != frames.len() {
+                    return Err(bad());
+                }
+                // Held keys and the cursor carry over; the wheel only turns for one frame.
+                let mut input = frames.last().map_or(InputFrame::default(), |f| f.input);
+                input.dt = values.parse().map_err(|_| bad())?;
+                input.wheel = 0.0;
+                frames.push(ReplayFrame { input, settings: None });
+                continue;
+            }
+            let count = frames.len();
+            let current = frames.last_mut().filter(|_| frame + 1 == count).ok_or_else(bad)?;
+            let number = |text: &str| text.parse::<f32>().map_err(|_| bad());
+            match event {
+                "mouse" => {
+                    let (x, y) = values.split_once(' ').ok_or_else(bad)?;
+                    current.input.mouse = vec2(number(x)?, number(y)?);
+                }
+                "wheel" => current.input.wheel = number(values)?,
+                "ui" => current.input.over_ui = values == "1",
+                "down" | "up" => {
+                    if !current.input.set_named(values, event == "down") {
+                        return Err(bad());
+                    }
+                }
+                "settings" => current.settings = Some(serde_json::from_str(values).map_err(|_| bad())?),
+                _ => return Err(bad())
+            }
+        }
+        let settings = frames.first_mut().and_then(|f| f.settings.take()).ok_or(ReplayError::BadLine(3))?;
+        Ok(Replay { start, settings, frames: frames.into_iter() })
+    }
+
+    pub fn next_frame(&mut self) -> Option<ReplayFrame> {
+        self.frames.next()
+    }
+}
@@ -0,0 +1,73 @@
+// The shaded draw mode's material. It lights the cloth per pixel from the triangle normals the
+// mesh carries in each vertex's `normal`, on both sides like the CPU-lit mesh, and adds a rim
+// glow where the fabric tilts edge-on to the viewer.
+
+use macroquad::prelude::*;
+
+use crate::AMBIENT_LIGHT;
+
+const VERTEX: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+attribute vec4 normal;
+
+varying lowp vec2 uv;
+varying lowp vec4 color;
+varying mediump vec3 surface_normal;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+    surface_normal = normal.xyz;
+}
+"#;
+
+const FRAGMENT: &str = r#"#version 100
+precision mediump float;
+
+varying lowp vec2 uv;
+varying lowp vec4 color;
+varying mediump vec3 surface_normal;
+
+uniform sampler2D Texture;
+uniform vec3 LightDir;
+uniform vec4 BaseColor;
+uniform float RimStrength;
+uniform float Ambient;
+
+void main() {
+    vec3 n = normalize(surface_normal);
+    float diffuse = Ambient + (1.0 - Ambient) * abs(dot(n, LightDir));
+    float rim = pow(1.0 - abs(n.z), 2.0) * RimStrength;
+    vec3 base = BaseColor.rgb * color.rgb * texture2D(Texture, uv).rgb;
+    gl_FragColor = vec4(base * diffuse + vec3(rim), 1.0);
+}
+"#;
+
+pub fn load_cloth_material() -> Result<Material, macroquad::Error> {
+    load_material(
+        ShaderSource::Glsl { vertex: VERTEX, fragment: FRAGMENT },
+        MaterialParams {
+            uniforms: vec![
+                UniformDesc::new("LightDir", UniformType::Float3),
+                UniformDesc::new("BaseColor", UniformType::Float4),
+                UniformDesc::new("RimStrength", UniformType::Float1),
+                UniformDesc::new("Ambient", UniformType::Float1)
+            ],
+            ..Default::default()
+        }
+    )
+}
+
+// Sets the uniforms; the material still has to be switched on with `gl_use_material`.
+pub fn set_cloth_uniforms(material: &Material, light: Vec3, base_color: Color, rim_strength: f32) {
+    material.set_uniform("LightDir", light);
+    material.set_uniform("BaseColor", vec4(base_color.r, base_color.g, base_color.b, base_color.a));
+    material.set_uniform("RimStrength", rim_strength);
+    material.set_uniform("Ambient", AMBIENT_LIGHT);
+}
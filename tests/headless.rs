@@ -0,0 +1,23 @@
+use cloth::{Cloth, ClothConfig, PinMode, SimParams, FIXED_DT};
+use glam::Vec2;
+
+// Runs the full update loop through the public API without a window; nothing in the physics may
+// need one.
+#[test]
+fn headless_cloth_sags_under_gravity() {
+    let config = ClothConfig { pin_mode: PinMode::TopCorners, ..ClothConfig::new(10, 10, 15.0, Vec2::ZERO) };
+    let mut cloth = Cloth::new(&config);
+    let bottom_center = 9 * 10 + 5;
+    let start = cloth.particles()[bottom_center].pos;
+    let params = SimParams { iterations: 5, parallel: false, ..SimParams::default() };
+
+    for _ in 0..100 {
+        cloth.update(FIXED_DT, &params);
+    }
+
+    // Reference value from the solver at the time this test was written.
+    let moved = cloth.particles()[bottom_center].pos - start;
+    assert!(moved.y > 0.0);
+    assert!((moved.y - 0.7427).abs() < 0.01, "bottom-center moved {:?}", moved);
+    assert!(moved.x.abs() < 0.01, "bottom-center moved {:?}", moved);
+}